@@ -1,5 +1,4 @@
 use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
-use serde::{Serialize, Deserialize};
 use serde_json::json;
 use mongodb::{Client, options::ClientOptions};
 use futures::stream::StreamExt;
@@ -9,48 +8,14 @@ use mongodb::bson::{self, Bson, Document};
 use mongodb::options::FindOptions;
 use once_cell::sync::Lazy;
 use std::sync::Mutex;
+use argo_schema::{DataSchema, HistoryRecord, QcFlag, TechnicalSchema, TrajectorySchema};
+use argo_schema::migrate::upgrade_profile;
 
 static CLIENT: Lazy<Mutex<Option<mongodb::Client>>> = Lazy::new(|| Mutex::new(None));
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct GeoJSONPoint {
-    #[serde(rename = "type")]
-    location_type: String,
-    coordinates: [f64; 2],
-} 
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct DataInfo {
-    DATA_MODE: String,
-    UNITS: String,
-    LONG_NAME: String,
-    PROFILE_PARAMETER_QC: String,
-} 
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct DataSchema {
-    _id: String,
-    geolocation: GeoJSONPoint,
-    metadata: Vec<String>,
-    CYCLE_NUMBER: i32,
-    DIRECTION: String,
-    DATA_STATE_INDICATOR: String,
-    DATA_MODE: String,
-    DATE_CREATION: String,
-    DATE_UPDATE: String,
-    DC_REFERENCE: String,
-    JULD: f64,
-    JULD_QC: String,
-    JULD_LOCATION: f64,
-    POSITION_QC: String,
-    VERTICAL_SAMPLING_SCHEME: String,
-    CONFIG_MISSION_NUMBER: i32,
-    STATION_PARAMETERS: Vec<String>,
-    realtime_data: Option<HashMap<String, Vec<f64>>>,
-    adjusted_data: Option<HashMap<String, Vec<f64>>>,
-    data_info: Option<HashMap<String, DataInfo>>,
-    level_qc: Option<HashMap<String, Vec<String>>>,
-    adjusted_level_qc: Option<HashMap<String, Vec<String>>>,
+// a Client is a handle onto a shared connection pool, so routes query through a clone instead of holding the lock across awaits
+fn client() -> mongodb::Client {
+    CLIENT.lock().unwrap().as_ref().unwrap().clone()
 }
 
 #[get("/query_params")]
@@ -66,8 +31,14 @@ async fn search_data_schema(query_params: web::Query<serde_json::Value>) -> impl
 
     // Extract the query parameters
     let polygon = query_params.get("polygon").map(|p| p.as_str().unwrap());
-    let startDate = query_params.get("startDate").map(|d| d.as_str().unwrap().parse::<f64>().unwrap());
-    let endDate = query_params.get("endDate").map(|d| d.as_str().unwrap().parse::<f64>().unwrap());
+    let startDate = match date_bound(&query_params, "startDate") {
+        Ok(bound) => bound,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+    let endDate = match date_bound(&query_params, "endDate") {
+        Ok(bound) => bound,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
 
     let mut data_map: HashMap<String, Vec<i32>> = HashMap::new();
     let mut current_key: Option<String> = None;
@@ -92,9 +63,36 @@ async fn search_data_schema(query_params: web::Query<serde_json::Value>) -> impl
         .map(|p| p.as_str().unwrap().split(',').map(|s| s.parse::<f64>().unwrap()).collect())
         .unwrap_or(Vec::new());
 
+    // history filters all have to match the same history record
+    let history_fields = [
+        ("historyInstitution", "HISTORY_INSTITUTION"),
+        ("historyStep", "HISTORY_STEP"),
+        ("historySoftware", "HISTORY_SOFTWARE"),
+        ("historyAction", "HISTORY_ACTION"),
+        ("historyParameter", "HISTORY_PARAMETER"),
+    ];
+    let mut history_match = mongodb::bson::doc! {};
+    for (param, field) in history_fields {
+        if let Some(value) = query_params.get(param).map(|d| d.as_str().unwrap()) {
+            history_match.insert(field, value);
+        }
+    }
+    // qcTestFailed is a hex bitmask of QC tests, as encoded in HISTORY_QCTEST
+    let qc_test_failed = match query_params.get("qcTestFailed").map(|d| d.as_str().and_then(|d| u64::from_str_radix(d, 16).ok())) {
+        Some(None) => return HttpResponse::BadRequest().body("qcTestFailed must be a hexadecimal bitmask"),
+        Some(mask) => mask,
+        None => None,
+    };
+    if qc_test_failed.is_some() {
+        // failed tests are only recorded on QCF$ records, so any other action can't match
+        if history_match.get_str("HISTORY_ACTION").is_ok_and(|action| action != "QCF$") {
+            return HttpResponse::BadRequest().body("qcTestFailed only applies to QCF$ history records, so historyAction must be QCF$ or left out");
+        }
+        history_match.insert("HISTORY_ACTION", "QCF$");
+    }
 
-    // Build the filter based on the provided parameters
-    let mut filter = mongodb::bson::doc! {};
+    // Build the filter based on the provided parameters, skipping profiles tombstoned by the converter
+    let mut filter = mongodb::bson::doc! { "deleted": { "$ne": true } };
 
     if let Some(polygon) = polygon {
         let polygon_coordinates: Vec<Vec<Vec<f64>>> = serde_json::from_str(polygon).unwrap();
@@ -105,18 +103,44 @@ async fn search_data_schema(query_params: web::Query<serde_json::Value>) -> impl
         filter.insert("geolocation", mongodb::bson::doc! { "$geoWithin": { "$geometry": polygon_geojson } });
     }
 
-    if let (Some(startDate), Some(endDate)) = (startDate, endDate) {
-        filter.insert("JULD", mongodb::bson::doc! { "$gte": startDate, "$lt": endDate });
-    } else if let Some(startDate) = startDate {
-        filter.insert("JULD", mongodb::bson::doc! { "$gte": startDate });
-    } else if let Some(endDate) = endDate {
-        filter.insert("JULD", mongodb::bson::doc! { "$lt": endDate });
+    // each bound filters JULD if it was given as a number, or timestamp if it was a date
+    let mut juld_range = mongodb::bson::doc! {};
+    let mut timestamp_range = mongodb::bson::doc! {};
+    for (operator, bound) in [("$gte", startDate), ("$lt", endDate)] {
+        match bound {
+            Some(DateBound::Juld(juld)) => { juld_range.insert(operator, juld); },
+            Some(DateBound::Timestamp(timestamp)) => { timestamp_range.insert(operator, timestamp); },
+            None => {},
+        }
+    }
+    if !juld_range.is_empty() {
+        filter.insert("JULD", juld_range);
+    }
+    if !timestamp_range.is_empty() {
+        filter.insert("timestamp", timestamp_range);
     }
 
     if !data.is_empty() {
         filter.insert("STATION_PARAMETERS", mongodb::bson::doc! { "$all": data });
     }
 
+    // provenance filters, e.g. to find everything a given converter release built
+    let provenance_fields = [
+        ("converterVersion", "provenance.converter_version"),
+        ("dac", "provenance.sources.dac"),
+        ("sourceFile", "provenance.sources.path"),
+        ("sourceChecksum", "provenance.sources.sha256"),
+    ];
+    for (param, field) in provenance_fields {
+        if let Some(value) = query_params.get(param).map(|d| d.as_str().unwrap()) {
+            filter.insert(field, value);
+        }
+    }
+
+    if !history_match.is_empty() {
+        filter.insert("history", mongodb::bson::doc! { "$elemMatch": history_match.clone() });
+    }
+
     // Search for documents with matching filters
     let mut cursor = {
         let mut options = FindOptions::builder()
@@ -126,14 +150,31 @@ async fn search_data_schema(query_params: web::Query<serde_json::Value>) -> impl
             .build();
         let guard = CLIENT.lock().unwrap();
         let client = guard.as_ref().unwrap();
-        client.database("argo").collection::<DataSchema>("argo").find(filter, options).await.unwrap()
+        client.database("argo").collection::<Document>("argo").find(filter, options).await.unwrap()
     }; // in theory the mutex is unlocked here, holding it as little as possible
     
     let mut results = Vec::new();
 
     while let Some(result) = cursor.next().await {
         match result {
-            Ok(mut document) => {
+            Ok(document) => {
+                // one unreadable document shouldn't fail the whole search
+                let mut document = match read_profile(document) {
+                    Ok(document) => document,
+                    Err(e) => {
+                        eprintln!("Skipping unreadable profile: {}", e);
+                        continue;
+                    }
+                };
+
+                // the bitmask can't be matched in the query, so check failed qc tests here,
+                // on a record that also meets the other history filters
+                if let Some(mask) = qc_test_failed {
+                    if !failed_qc_test(document.history.as_deref().unwrap_or(&[]), &history_match, mask) {
+                        continue;
+                    }
+                }
+
                 // qc filtering
                 for (key, qc_values) in &data_map {
                     if !qc_values.is_empty() {
@@ -144,6 +185,13 @@ async fn search_data_schema(query_params: web::Query<serde_json::Value>) -> impl
                                 }
                             }
                         }
+                        if let Some(dpres) = &mut document.dpres {
+                            if let Some(level_qc) = document.level_qc.as_ref() {
+                                if let Some(level_qc_values) = level_qc.get(key) {
+                                    apply_qc_filter(dpres, &level_qc_values.clone(), qc_values);
+                                }
+                            }
+                        }
                         if let Some(adjusted_data) = &mut document.adjusted_data {
                             if let Some(adjusted_level_qc) = document.adjusted_level_qc.as_ref() {
                                 if let Some(adjusted_level_qc_values) = adjusted_level_qc.get(key) {
@@ -151,6 +199,13 @@ async fn search_data_schema(query_params: web::Query<serde_json::Value>) -> impl
                                 }
                             }
                         }
+                        if let Some(adjusted_error) = &mut document.adjusted_error {
+                            if let Some(adjusted_level_qc) = document.adjusted_level_qc.as_ref() {
+                                if let Some(adjusted_level_qc_values) = adjusted_level_qc.get(key) {
+                                    apply_qc_filter(adjusted_error, &adjusted_level_qc_values.clone(), qc_values);
+                                }
+                            }
+                        }
                         if let Some(level_qc) = &mut document.level_qc {
                             if let Some(level_qc_values) = level_qc.get(key) {
                                 apply_qc_filter(level_qc, &level_qc_values.clone(), qc_values);
@@ -174,6 +229,9 @@ async fn search_data_schema(query_params: web::Query<serde_json::Value>) -> impl
                             if let Some(level_qc) = &mut document.level_qc {
                                 apply_pressure_range(level_qc, &pressures, &pres_range);
                             }
+                            if let Some(dpres) = &mut document.dpres {
+                                apply_pressure_range(dpres, &pressures, &pres_range);
+                            }
                         }
                     }
                     if let Some(adjusted_data) = &mut document.adjusted_data {
@@ -183,17 +241,21 @@ async fn search_data_schema(query_params: web::Query<serde_json::Value>) -> impl
                             if let Some(adjusted_level_qc) = &mut document.adjusted_level_qc {
                                 apply_pressure_range(adjusted_level_qc, &pressures, &pres_range);
                             }
+                            if let Some(adjusted_error) = &mut document.adjusted_error {
+                                apply_pressure_range(adjusted_error, &pressures, &pres_range);
+                            }
                         }
                     }
                 }
 
                 // only push the document if it still has data for every requested data value after depth and qc filtering
+                // levels that were fill values in the source file don't count
                 let mut should_push = true;
                 for key in data_map.keys() {
                     let realtime_data_empty = document.realtime_data.as_ref()
-                        .map_or(true, |data| data.get(key).map_or(true, Vec::is_empty));
+                        .map_or(true, |data| data.get(key).map_or(true, |values| values.iter().all(Option::is_none)));
                     let adjusted_data_empty = document.adjusted_data.as_ref()
-                        .map_or(true, |data| data.get(key).map_or(true, Vec::is_empty));
+                        .map_or(true, |data| data.get(key).map_or(true, |values| values.iter().all(Option::is_none)));
                 
                     if realtime_data_empty && adjusted_data_empty {
                         should_push = false;
@@ -214,6 +276,154 @@ async fn search_data_schema(query_params: web::Query<serde_json::Value>) -> impl
     HttpResponse::Ok().json(results)
 }
 
+#[get("/calibration/{id}")]
+async fn get_calibration(path: web::Path<String>) -> impl Responder {
+    let id = path.into_inner();
+
+    let result = client().database("argo").collection::<Document>("argo").find_one(mongodb::bson::doc! { "_id": &id, "deleted": { "$ne": true } }, None).await;
+
+    match result.map_err(Box::from).and_then(|document| document.map(read_profile).transpose()) {
+        Ok(Some(document)) => HttpResponse::Ok().json(document.calibration.unwrap_or_default()),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// ids and sources of every profile a converter version built, as a list of files to re-ingest
+#[get("/provenance/{converter_version}")]
+async fn get_provenance(path: web::Path<String>) -> impl Responder {
+    let converter_version = path.into_inner();
+
+    let mut cursor = {
+        let options = FindOptions::builder()
+            .projection(mongodb::bson::doc! { "provenance": 1 })
+            .build();
+        match client().database("argo").collection::<Document>("argo").find(mongodb::bson::doc! { "provenance.converter_version": &converter_version, "deleted": { "$ne": true } }, options).await {
+            Ok(cursor) => cursor,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+    };
+
+    let mut results = Vec::new();
+    while let Some(result) = cursor.next().await {
+        match result {
+            Ok(document) => results.push(document),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+    }
+
+    HttpResponse::Ok().json(results)
+}
+
+#[get("/trajectory/{platform}")]
+async fn get_trajectory(path: web::Path<String>) -> impl Responder {
+    let platform = path.into_inner();
+
+    let mut cursor = {
+        let options = FindOptions::builder()
+            .sort(mongodb::bson::doc! { "CYCLE_NUMBER": 1 })
+            .build();
+        match client().database("argo").collection::<TrajectorySchema>("argoTrajectory").find(mongodb::bson::doc! { "PLATFORM_NUMBER": &platform }, options).await {
+            Ok(cursor) => cursor,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+    };
+
+    // every located, timestamped fix that wasn't flagged bad becomes a vertex
+    let mut vertices: Vec<(f64, f64, f64, i32, i32)> = Vec::new();
+    while let Some(result) = cursor.next().await {
+        match result {
+            Ok(cycle) => {
+                for measurement in cycle.measurements {
+                    if let (Some(juld), Some(lat), Some(lon)) = (measurement.JULD, measurement.LATITUDE, measurement.LONGITUDE) {
+                        if measurement.POSITION_QC != QcFlag::Bad {
+                            vertices.push((juld, lon, lat, cycle.CYCLE_NUMBER, measurement.MEASUREMENT_CODE));
+                        }
+                    }
+                }
+            },
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+    }
+
+    if vertices.is_empty() {
+        return HttpResponse::NotFound().finish();
+    }
+    vertices.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    // a LineString needs two positions, so a float with a single fix is a Point
+    let geometry = if vertices.len() == 1 {
+        json!({ "type": "Point", "coordinates": [vertices[0].1, vertices[0].2] })
+    } else {
+        json!({ "type": "LineString", "coordinates": vertices.iter().map(|v| [v.1, v.2]).collect::<Vec<_>>() })
+    };
+
+    HttpResponse::Ok().json(json!({
+        "type": "Feature",
+        "geometry": geometry,
+        "properties": {
+            "PLATFORM_NUMBER": platform,
+            "timestamp": vertices.iter().map(|v| juld_to_iso(v.0)).collect::<Vec<_>>(),
+            "CYCLE_NUMBER": vertices.iter().map(|v| v.3).collect::<Vec<_>>(),
+            "MEASUREMENT_CODE": vertices.iter().map(|v| v.4).collect::<Vec<_>>()
+        }
+    }))
+}
+
+#[get("/technical/{platform}/{parameter}")]
+async fn get_technical_series(path: web::Path<(String, String)>) -> impl Responder {
+    let (platform, parameter) = path.into_inner();
+
+    let mut cursor = {
+        let options = FindOptions::builder()
+            .sort(mongodb::bson::doc! { "CYCLE_NUMBER": 1 })
+            .build();
+        // the parameter is looked up in each cycle below, rather than becoming a field path that a . or $ would break
+        match client().database("argo").collection::<TechnicalSchema>("argoTechnical").find(mongodb::bson::doc! { "PLATFORM_NUMBER": &platform }, options).await {
+            Ok(cursor) => cursor,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+    };
+
+    // numeric and text values both make up the series
+    let mut series = Vec::new();
+    while let Some(result) = cursor.next().await {
+        match result {
+            Ok(cycle) => {
+                if let Some(value) = cycle.parameters.get(&parameter) {
+                    series.push(json!({ "CYCLE_NUMBER": cycle.CYCLE_NUMBER, "value": value }));
+                } else if let Some(value) = cycle.text_parameters.get(&parameter) {
+                    series.push(json!({ "CYCLE_NUMBER": cycle.CYCLE_NUMBER, "value": value }));
+                }
+            },
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+    }
+
+    HttpResponse::Ok().json(series)
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
 
@@ -228,46 +438,137 @@ async fn main() -> std::io::Result<()> {
         App::new()
             .service(get_query_params)
             .service(search_data_schema)
+            .service(get_calibration)
+            .service(get_provenance)
+            .service(get_trajectory)
+            .service(get_technical_series)
     })
     .bind(("0.0.0.0", 8080))?
     .run()
     .await
 }
 
-fn slice_vector_by_pressure_range<T: Clone>(pres_range: &[f64], pressures: &[f64], values: &[T]) -> Vec<T> {
-    if pressures.is_empty() || values.is_empty() || pres_range[0] > pressures[pressures.len() - 1] || pres_range[1] < pressures[0]{
-        return Vec::new();
-    }
+// documents written before the last migration are upgraded as they're read, so the API keeps working during a rollout
+fn read_profile(mut document: Document) -> Result<DataSchema, Box<dyn std::error::Error>> {
+    upgrade_profile(&mut document)?;
+    Ok(bson::from_document(document)?)
+}
 
-    let start_index = pressures.iter().position(|&p| p >= pres_range[0]).unwrap_or(0);
-    let end_index = pressures.iter().rposition(|&p| p < pres_range[1]).unwrap_or_else(|| pressures.len() - 1);
+// trajectory JULD is days since 1950-01-01T00:00:00Z, the reference date every trajectory file uses
+fn juld_to_iso(juld: f64) -> Option<String> {
+    const EPOCH_1950_MILLIS: i64 = -631_152_000_000;
+    bson::DateTime::from_millis(EPOCH_1950_MILLIS + (juld * 86_400_000.0).round() as i64).try_to_rfc3339_string().ok()
+}
 
-    if start_index > end_index {
-        Vec::new()
-    } else {
-        values[start_index..=end_index].to_vec()
+enum DateBound {
+    Juld(f64),
+    Timestamp(bson::DateTime),
+}
+
+// startDate and endDate take raw JULD days, or ISO-8601 dates like 2020-01-01, 2020-01-01T12:00:00Z
+// or 2020-01-01T12:00:00+02:00; a date or time without a zone is taken as UTC
+fn parse_date_bound(value: &str) -> Option<DateBound> {
+    if let Ok(juld) = value.parse::<f64>() {
+        return Some(DateBound::Juld(juld));
+    }
+    let value = match value.split_once('T') {
+        None => format!("{}T00:00:00Z", value),
+        Some((_, time)) if !time.ends_with('Z') && !time.contains('+') && !time.contains('-') => format!("{}Z", value),
+        Some(_) => value.to_string(),
+    };
+    bson::DateTime::parse_rfc3339_str(&value).ok().map(DateBound::Timestamp)
+}
+
+// a malformed bound is the caller's mistake, so it's a 400 rather than a panic
+fn date_bound(query_params: &serde_json::Value, param: &str) -> Result<Option<DateBound>, String> {
+    match query_params.get(param).map(|value| value.as_str().and_then(parse_date_bound)) {
+        Some(None) => Err(format!("{} must be a JULD day number or an ISO-8601 date", param)),
+        Some(bound) => Ok(bound),
+        None => Ok(None),
     }
 }
 
-fn apply_pressure_range<T: Clone + 'static>(data: &mut HashMap<String, Vec<T>>, pressures: &[f64], pres_range: &[f64]) {
+fn slice_vector_by_pressure_range<T: Clone>(pres_range: &[f64], pressures: &[Option<f64>], values: &[T]) -> Vec<T> {
+    // levels with a missing pressure can't be placed in the range, so they're dropped
+    pressures.iter()
+        .zip(values)
+        .filter(|(p, _)| p.map_or(false, |p| p >= pres_range[0] && p < pres_range[1]))
+        .map(|(_, value)| value.clone())
+        .collect()
+}
+
+fn apply_pressure_range<T: Clone + 'static>(data: &mut HashMap<String, Vec<T>>, pressures: &[Option<f64>], pres_range: &[f64]) {
     for (key, values) in data.iter_mut() {
         *values = slice_vector_by_pressure_range(pres_range, pressures, values);
     }
 }
 
-fn qc_filter<T: Clone>(qc_values: &[String], data: &[T], acceptable_qc: &[i32]) -> Vec<T> {
+fn qc_filter<T: Clone>(qc_values: &[QcFlag], data: &[T], acceptable_qc: &[i32]) -> Vec<T> {
     if data.is_empty() {
         return Vec::new();
     }
 
     qc_values.iter()
         .enumerate()
-        .filter_map(|(i, qc)| qc.parse::<i32>().ok().and_then(|qc| if acceptable_qc.contains(&qc) { Some(data[i].clone()) } else { None }))
+        .filter_map(|(i, qc)| qc.value().and_then(|qc| if acceptable_qc.contains(&qc) { Some(data[i].clone()) } else { None }))
         .collect()
 }
 
-fn apply_qc_filter<T: Clone>(data: &mut HashMap<String, Vec<T>>, qc_data: &[String], acceptable_qc: &[i32]) {
+fn apply_qc_filter<T: Clone>(data: &mut HashMap<String, Vec<T>>, qc_data: &[QcFlag], acceptable_qc: &[i32]) {
     for values in data.values_mut() {
         *values = qc_filter(qc_data, values, acceptable_qc);
     }
-}
\ No newline at end of file
+}
+
+// the history filters' fields, by name
+fn history_field<'a>(record: &'a HistoryRecord, field: &str) -> Option<&'a str> {
+    match field {
+        "HISTORY_INSTITUTION" => Some(&record.HISTORY_INSTITUTION),
+        "HISTORY_STEP" => Some(&record.HISTORY_STEP),
+        "HISTORY_SOFTWARE" => Some(&record.HISTORY_SOFTWARE),
+        "HISTORY_ACTION" => Some(&record.HISTORY_ACTION),
+        "HISTORY_PARAMETER" => Some(&record.HISTORY_PARAMETER),
+        _ => None,
+    }
+}
+
+// history_match is the $elemMatch the query used, so the mask is checked on a record it matched
+fn failed_qc_test(history: &[HistoryRecord], history_match: &Document, mask: u64) -> bool {
+    history.iter()
+        .filter(|record| history_match.iter().all(|(field, value)| value.as_str().is_some() && history_field(record, field) == value.as_str()))
+        .filter_map(|record| u64::from_str_radix(&record.HISTORY_QCTEST, 16).ok())
+        .any(|tests| tests & mask != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamp(value: &str) -> Option<i64> {
+        match parse_date_bound(value) {
+            Some(DateBound::Timestamp(timestamp)) => Some(timestamp.timestamp_millis()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn date_bounds() {
+        assert!(matches!(parse_date_bound("25567.5"), Some(DateBound::Juld(juld)) if juld == 25567.5));
+        let noon = Some(1_577_880_000_000);
+        assert_eq!(timestamp("2020-01-01"), Some(1_577_836_800_000));
+        assert_eq!(timestamp("2020-01-01T12:00:00Z"), noon);
+        assert_eq!(timestamp("2020-01-01T12:00:00"), noon);
+        assert_eq!(timestamp("2020-01-01T12:00:00.000"), noon);
+        assert_eq!(timestamp("2020-01-01T14:00:00+02:00"), noon);
+        assert_eq!(timestamp("2020-01-01T10:00:00-02:00"), noon);
+        for malformed in ["", "yesterday", "2020-13-01", "2020-01-01T25:00:00", "01/01/2020"] {
+            assert!(parse_date_bound(malformed).is_none(), "{} parsed", malformed);
+        }
+    }
+
+    #[test]
+    fn trajectory_times_are_iso_dates() {
+        assert_eq!(juld_to_iso(0.0).as_deref(), Some("1950-01-01T00:00:00Z"));
+        assert_eq!(juld_to_iso(25567.5).as_deref(), Some("2020-01-01T12:00:00Z"));
+    }
+}
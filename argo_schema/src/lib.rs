@@ -0,0 +1,359 @@
+#![allow(nonstandard_style)]
+// the stored document model, shared by convert_nc, which writes it, and admt_api, which serves it
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+pub mod migrate;
+
+// bumped whenever a change to these structs changes what's stored, with a step in migrate to match
+pub const SCHEMA_VERSION: i32 = 2;
+
+fn unversioned() -> i32 {
+    migrate::UNVERSIONED
+}
+
+// flag values //////////////////////////////////////////////////
+
+// each flag serializes as the character the netCDF files use, so stored documents read the same as the source;
+// a blank or unrecognised character is Blank
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DataMode {
+    #[serde(rename = "R")]
+    RealTime,
+    #[serde(rename = "A")]
+    Adjusted,
+    #[serde(rename = "D")]
+    Delayed,
+    #[default]
+    #[serde(rename = "")]
+    Blank,
+}
+
+impl DataMode {
+    pub fn parse(flag: &str) -> DataMode {
+        match flag.trim() {
+            "R" => DataMode::RealTime,
+            "A" => DataMode::Adjusted,
+            "D" => DataMode::Delayed,
+            _ => DataMode::Blank,
+        }
+    }
+
+    pub fn is_valid(flag: &str) -> bool {
+        ["R", "A", "D"].contains(&flag.trim())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Direction {
+    #[serde(rename = "A")]
+    Ascending,
+    #[serde(rename = "D")]
+    Descending,
+    #[default]
+    #[serde(rename = "")]
+    Blank,
+}
+
+impl Direction {
+    pub fn parse(flag: &str) -> Direction {
+        match flag.trim() {
+            "A" => Direction::Ascending,
+            "D" => Direction::Descending,
+            _ => Direction::Blank,
+        }
+    }
+
+    pub fn is_valid(flag: &str) -> bool {
+        ["A", "D"].contains(&flag.trim())
+    }
+}
+
+// reference table 2
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum QcFlag {
+    #[serde(rename = "0")]
+    NoQc,
+    #[serde(rename = "1")]
+    Good,
+    #[serde(rename = "2")]
+    ProbablyGood,
+    #[serde(rename = "3")]
+    ProbablyBad,
+    #[serde(rename = "4")]
+    Bad,
+    #[serde(rename = "5")]
+    Changed,
+    #[serde(rename = "6")]
+    Unused6,
+    #[serde(rename = "7")]
+    Unused7,
+    #[serde(rename = "8")]
+    Estimated,
+    #[serde(rename = "9")]
+    Missing,
+    #[default]
+    #[serde(rename = "")]
+    Blank,
+}
+
+impl QcFlag {
+    pub fn parse(flag: &str) -> QcFlag {
+        match flag.trim() {
+            "0" => QcFlag::NoQc,
+            "1" => QcFlag::Good,
+            "2" => QcFlag::ProbablyGood,
+            "3" => QcFlag::ProbablyBad,
+            "4" => QcFlag::Bad,
+            "5" => QcFlag::Changed,
+            "6" => QcFlag::Unused6,
+            "7" => QcFlag::Unused7,
+            "8" => QcFlag::Estimated,
+            "9" => QcFlag::Missing,
+            _ => QcFlag::Blank,
+        }
+    }
+
+    // blank is allowed, it's the fill value
+    pub fn is_valid(flag: &str) -> bool {
+        let flag = flag.trim();
+        flag.is_empty() || (flag.len() == 1 && flag.chars().all(|c| c.is_ascii_digit()))
+    }
+
+    // the flag's number, as the API's qc filters take them
+    pub fn value(&self) -> Option<i32> {
+        match self {
+            QcFlag::NoQc => Some(0),
+            QcFlag::Good => Some(1),
+            QcFlag::ProbablyGood => Some(2),
+            QcFlag::ProbablyBad => Some(3),
+            QcFlag::Bad => Some(4),
+            QcFlag::Changed => Some(5),
+            QcFlag::Unused6 => Some(6),
+            QcFlag::Unused7 => Some(7),
+            QcFlag::Estimated => Some(8),
+            QcFlag::Missing => Some(9),
+            QcFlag::Blank => None,
+        }
+    }
+
+    // how much a flag casts doubt on a value, for picking the worst of several; missing and blank (fill) rank last,
+    // and a value no one has checked is worse than one judged probably good
+    fn severity(&self) -> u8 {
+        match self {
+            QcFlag::Good => 0,
+            QcFlag::Changed => 1,
+            QcFlag::Estimated => 2,
+            QcFlag::ProbablyGood => 3,
+            QcFlag::Unused6 | QcFlag::Unused7 => 4,
+            QcFlag::NoQc => 5,
+            QcFlag::ProbablyBad => 6,
+            QcFlag::Bad => 7,
+            QcFlag::Missing => 8,
+            QcFlag::Blank => 9,
+        }
+    }
+
+    // the flag for a value derived from several others
+    pub fn worst(flags: impl IntoIterator<Item = QcFlag>) -> QcFlag {
+        flags.into_iter().max_by_key(QcFlag::severity).unwrap_or(QcFlag::Blank)
+    }
+}
+
+// profiles: the argo collection //////////////////////////////////
+
+// R and D files for the same cycle share one document, so the data mode letter after any B or S prefix is dropped
+// from the file stem; ids the first converter wrote, like R4902911_001, map onto the shared one the same way
+pub fn collapse_profile_id(id: &str) -> String {
+    let (prefix, rest) = if id.starts_with('B') || id.starts_with('S') {
+        id.split_at(1)
+    } else {
+        ("", id)
+    };
+    format!("{}{}", prefix, rest.trim_start_matches(['R', 'D']))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeoJSONPoint {
+    #[serde(rename = "type")]
+    pub location_type: String,
+    pub coordinates: [f64; 2],
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DataInfo {
+    pub DATA_MODE: DataMode,
+    pub UNITS: String,
+    pub LONG_NAME: String,
+    pub PROFILE_PARAMETER_QC: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryRecord {
+    pub HISTORY_INSTITUTION: String,
+    pub HISTORY_STEP: String,
+    pub HISTORY_SOFTWARE: String,
+    pub HISTORY_SOFTWARE_RELEASE: String,
+    pub HISTORY_REFERENCE: String,
+    pub HISTORY_DATE: String,
+    pub HISTORY_ACTION: String,
+    pub HISTORY_PARAMETER: String,
+    pub HISTORY_START_PRES: Option<f64>,
+    pub HISTORY_STOP_PRES: Option<f64>,
+    pub HISTORY_PREVIOUS_VALUE: Option<f64>,
+    pub HISTORY_QCTEST: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CalibrationRecord {
+    pub SCIENTIFIC_CALIB_EQUATION: String,
+    pub SCIENTIFIC_CALIB_COEFFICIENT: String,
+    pub SCIENTIFIC_CALIB_COMMENT: String,
+    pub SCIENTIFIC_CALIB_DATE: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SourceFile {
+    pub path: String, // relative to the data directory
+    pub dac: String,
+    pub sha256: String,
+}
+
+// where a document came from and what built it, so documents from a bad converter release can be found and rebuilt
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Provenance {
+    pub sources: Vec<SourceFile>, // the core file first, then any B-file merged into it
+    pub converter_version: String,
+    pub ingested: bson::DateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DataSchema {
+    pub _id: String,
+    #[serde(default = "unversioned")]
+    pub schema_version: i32,
+    pub geolocation: Option<GeoJSONPoint>,
+    #[serde(default)]
+    pub position_missing: bool,
+    #[serde(default)]
+    pub time_missing: bool,
+    pub metadata: Vec<String>,
+    pub CYCLE_NUMBER: Option<i32>,
+    pub DIRECTION: Direction,
+    pub DATA_STATE_INDICATOR: String,
+    pub DATA_MODE: DataMode,
+    pub DATE_CREATION: String,
+    pub DATE_UPDATE: String,
+    pub DC_REFERENCE: String,
+    pub JULD: Option<f64>,
+    pub JULD_QC: QcFlag,
+    pub JULD_LOCATION: Option<f64>,
+    // JULD, JULD_LOCATION, DATE_CREATION and DATE_UPDATE as datetimes, for range queries
+    pub timestamp: Option<bson::DateTime>,
+    pub location_timestamp: Option<bson::DateTime>,
+    pub creation_timestamp: Option<bson::DateTime>,
+    pub update_timestamp: Option<bson::DateTime>,
+    pub POSITION_QC: QcFlag,
+    pub VERTICAL_SAMPLING_SCHEME: String,
+    pub CONFIG_MISSION_NUMBER: Option<i32>,
+    pub STATION_PARAMETERS: Vec<String>,
+    // fill values are stored as nulls, so levels line up across parameters and QC arrays
+    pub realtime_data: Option<HashMap<String, Vec<Option<f64>>>>,
+    pub adjusted_data: Option<HashMap<String, Vec<Option<f64>>>>,
+    pub adjusted_error: Option<HashMap<String, Vec<Option<f64>>>>,
+    pub data_info: Option<HashMap<String, DataInfo>>,
+    pub level_qc: Option<HashMap<String, Vec<QcFlag>>>,
+    pub adjusted_level_qc: Option<HashMap<String, Vec<QcFlag>>>,
+    pub dpres: Option<HashMap<String, Vec<Option<f64>>>>,
+    pub history: Option<Vec<HistoryRecord>>,
+    pub calibration: Option<HashMap<String, Vec<CalibrationRecord>>>,
+    pub provenance: Option<Provenance>,
+}
+
+// float metadata: the argoMeta collection ////////////////////////
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SensorRecord {
+    pub SENSOR: String,
+    pub SENSOR_MAKER: String,
+    pub SENSOR_MODEL: String,
+    pub SENSOR_SERIAL_NO: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MetaSchema {
+    pub _id: String,
+    #[serde(default = "unversioned")]
+    pub schema_version: i32,
+    pub DATA_TYPE: String,
+    pub FORMAT_VERSION: String,
+    pub HANDBOOK_VERSION: String,
+    pub REFERENCE_DATE_TIME: String,
+    pub PROJECT_NAME: String,
+    pub PI_NAME: Vec<String>,
+    pub DATA_CENTRE: String,
+    pub PLATFORM_TYPE: String,
+    pub FLOAT_SERIAL_NO: String,
+    pub FIRMWARE_VERSION: String,
+    pub WMO_INST_TYPE: String,
+    pub POSITIONING_SYSTEM: String,
+    // the rest only come from _meta.nc files
+    pub PLATFORM_NUMBER: Option<String>,
+    pub PLATFORM_MAKER: Option<String>,
+    pub BATTERY_TYPE: Option<String>,
+    pub BATTERY_PACKS: Option<String>,
+    pub LAUNCH_DATE: Option<String>,
+    pub LAUNCH_LATITUDE: Option<f64>,
+    pub LAUNCH_LONGITUDE: Option<f64>,
+    pub LAUNCH_QC: Option<String>,
+    pub START_DATE: Option<String>,
+    pub END_MISSION_DATE: Option<String>,
+    pub END_MISSION_STATUS: Option<String>,
+    pub sensors: Option<Vec<SensorRecord>>,
+    pub launch_config: Option<HashMap<String, f64>>,
+    pub CONFIG_MISSION_NUMBER: Option<i32>,
+    pub CONFIG_MISSION_COMMENT: Option<String>,
+    pub config: Option<HashMap<String, f64>>,
+}
+
+// trajectories: the argoTrajectory collection ////////////////////
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrajectoryMeasurement {
+    pub JULD: Option<f64>,
+    pub JULD_QC: QcFlag,
+    pub LATITUDE: Option<f64>,
+    pub LONGITUDE: Option<f64>,
+    pub POSITION_ACCURACY: String,
+    pub POSITION_QC: QcFlag,
+    pub MEASUREMENT_CODE: i32,
+    pub data: HashMap<String, f64>,
+}
+
+// one document per float cycle, keeping long-lived floats well under the document size limit
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrajectorySchema {
+    pub _id: String,
+    pub PLATFORM_NUMBER: String,
+    pub CYCLE_NUMBER: i32,
+    pub DATA_MODE: DataMode,
+    pub CONFIG_MISSION_NUMBER: Option<i32>,
+    pub GROUNDED: String,
+    pub timing: HashMap<String, f64>,
+    pub measurements: Vec<TrajectoryMeasurement>,
+}
+
+// engineering data: the argoTechnical collection /////////////////
+
+// one document per float cycle; values that parse as numbers are kept typed, the rest as text
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TechnicalSchema {
+    pub _id: String,
+    pub PLATFORM_NUMBER: String,
+    pub CYCLE_NUMBER: i32,
+    pub parameters: HashMap<String, f64>,
+    pub text_parameters: HashMap<String, String>,
+}
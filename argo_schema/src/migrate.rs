@@ -0,0 +1,233 @@
+// upgrades stored documents one schema version at a time; convert_nc's migrate mode rewrites them in place,
+// and admt_api applies the same steps in memory to anything it reads that hasn't been migrated yet
+
+use std::error::Error;
+
+use bson::{Bson, Document};
+
+use crate::{collapse_profile_id, SCHEMA_VERSION};
+
+// documents written before schema_version was recorded
+pub const UNVERSIONED: i32 = 1;
+
+type Step = fn(&mut Document) -> Result<(), Box<dyn Error>>;
+
+// STEPS[i] takes a document from version UNVERSIONED + i to the next one
+const PROFILE_STEPS: [Step; 1] = [profile_v1_to_v2];
+const META_STEPS: [Step; 1] = [meta_v1_to_v2];
+
+// milliseconds from the unix epoch back to 1950-01-01, the Argo reference date
+const REFERENCE_MILLIS: i64 = -631_152_000_000;
+
+pub fn document_version(document: &Document) -> i32 {
+    match document.get("schema_version") {
+        Some(Bson::Int32(version)) => *version,
+        Some(Bson::Int64(version)) => *version as i32,
+        Some(Bson::Double(version)) => *version as i32,
+        _ => UNVERSIONED,
+    }
+}
+
+// true if the document was changed
+fn upgrade(document: &mut Document, steps: &[Step]) -> Result<bool, Box<dyn Error>> {
+    let version = document_version(document);
+    if version > SCHEMA_VERSION {
+        return Err(format!("schema_version {} is newer than this build's {}", version, SCHEMA_VERSION).into());
+    }
+    for from in version..SCHEMA_VERSION {
+        steps[(from - UNVERSIONED) as usize](document)?;
+        document.insert("schema_version", from + 1);
+    }
+    Ok(version < SCHEMA_VERSION)
+}
+
+// argo collection
+pub fn upgrade_profile(document: &mut Document) -> Result<bool, Box<dyn Error>> {
+    upgrade(document, &PROFILE_STEPS)
+}
+
+// argoMeta collection
+pub fn upgrade_meta(document: &mut Document) -> Result<bool, Box<dyn Error>> {
+    upgrade(document, &META_STEPS)
+}
+
+// version 1 -> 2 //////////////////////////////////////////////
+
+// version 1 kept netCDF fill values as numbers (99999, or 999999 for JULD) and had no datetimes, and the first converter
+// keyed profiles by file stem, R4902911_001, where R and D files now share 4902911_001
+
+fn is_fill(value: &Bson) -> bool {
+    match value {
+        Bson::Double(value) => *value >= 99999.0,
+        Bson::Int32(value) => *value == 99999,
+        Bson::Int64(value) => *value == 99999,
+        _ => false,
+    }
+}
+
+fn null_fill(document: &mut Document, key: &str) {
+    if document.get(key).is_some_and(is_fill) {
+        document.insert(key, Bson::Null);
+    }
+}
+
+fn juld_to_datetime(juld: f64) -> bson::DateTime {
+    bson::DateTime::from_millis(REFERENCE_MILLIS + (juld * 86_400_000.0).round() as i64)
+}
+
+// DATE_CREATION and DATE_UPDATE are YYYYMMDDHHMISS
+fn argo_date_to_datetime(date: &str) -> Option<bson::DateTime> {
+    if date.len() != 14 || !date.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let iso = format!("{}-{}-{}T{}:{}:{}Z", &date[0..4], &date[4..6], &date[6..8], &date[8..10], &date[10..12], &date[12..14]);
+    bson::DateTime::parse_rfc3339_str(&iso).ok()
+}
+
+fn profile_v1_to_v2(document: &mut Document) -> Result<(), Box<dyn Error>> {
+    // an _id can't be changed in place, so convert_nc's migrate stores the upgraded document under the new id
+    // and removes the old one
+    let id = collapse_profile_id(document.get_str("_id")?);
+    document.insert("_id", id);
+
+    for key in ["JULD", "JULD_LOCATION", "CYCLE_NUMBER", "CONFIG_MISSION_NUMBER"] {
+        null_fill(document, key);
+    }
+    for key in ["realtime_data", "adjusted_data", "adjusted_error"] {
+        if let Ok(data) = document.get_document_mut(key) {
+            for (_, values) in data.iter_mut() {
+                if let Bson::Array(values) = values {
+                    for value in values.iter_mut().filter(|value| is_fill(value)) {
+                        *value = Bson::Null;
+                    }
+                }
+            }
+        }
+    }
+
+    // version 1 stored an unknown position as the South Pole, [0, -90]
+    let position_missing = match document.get_document("geolocation").and_then(|point| point.get_array("coordinates")) {
+        Ok(coordinates) => coordinates.iter().any(is_fill) || coordinates.as_slice() == [Bson::Double(0.0), Bson::Double(-90.0)],
+        Err(_) => true,
+    };
+    if position_missing {
+        document.insert("geolocation", Bson::Null);
+    }
+    document.insert("position_missing", position_missing);
+
+    let juld = document.get_f64("JULD").ok();
+    document.insert("time_missing", juld.is_none());
+    if !document.contains_key("timestamp") {
+        document.insert("timestamp", juld.map(juld_to_datetime));
+    }
+    if !document.contains_key("location_timestamp") {
+        document.insert("location_timestamp", document.get_f64("JULD_LOCATION").ok().map(juld_to_datetime));
+    }
+    for (key, date) in [("creation_timestamp", "DATE_CREATION"), ("update_timestamp", "DATE_UPDATE")] {
+        if !document.contains_key(key) {
+            let datetime = document.get_str(date).ok().and_then(argo_date_to_datetime);
+            document.insert(key, datetime);
+        }
+    }
+    Ok(())
+}
+
+// metadata documents didn't change shape; the version is just recorded
+fn meta_v1_to_v2(_document: &mut Document) -> Result<(), Box<dyn Error>> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+
+    use super::*;
+    use crate::{DataSchema, QcFlag};
+
+    // a profile as the original converter stored it, before schema_version
+    fn version_1_profile(longitude: f64, latitude: f64, juld: f64) -> Document {
+        doc! {
+            "_id": "R4902911_001",
+            "geolocation": { "type": "Point", "coordinates": [longitude, latitude] },
+            "metadata": ["4902911_m0"],
+            "CYCLE_NUMBER": 1,
+            "DIRECTION": "A",
+            "DATA_STATE_INDICATOR": "2B",
+            "DATA_MODE": "R",
+            "DATE_CREATION": "20200101120000",
+            "DATE_UPDATE": "20200102130405",
+            "DC_REFERENCE": "",
+            "JULD": juld,
+            "JULD_QC": "1",
+            "JULD_LOCATION": juld,
+            "POSITION_QC": "1",
+            "VERTICAL_SAMPLING_SCHEME": "Primary sampling: averaged",
+            "CONFIG_MISSION_NUMBER": 99999,
+            "STATION_PARAMETERS": ["PRES", "TEMP"],
+            "realtime_data": { "PRES": [5.0, 10.0, 99999.0], "TEMP": [20.5, 99999.0, 99999.0] },
+            "adjusted_data": { "PRES": [], "TEMP": [] },
+            "data_info": {
+                "PRES": { "DATA_MODE": "", "UNITS": "", "LONG_NAME": "", "PROFILE_PARAMETER_QC": "" },
+                "TEMP": { "DATA_MODE": "", "UNITS": "", "LONG_NAME": "", "PROFILE_PARAMETER_QC": "" },
+            },
+            "level_qc": { "PRES": ["1", "1", ""], "TEMP": ["1", "9", ""] },
+            "adjusted_level_qc": { "PRES": [], "TEMP": [] },
+        }
+    }
+
+    #[test]
+    fn upgrades_a_version_1_profile() {
+        let mut document = version_1_profile(-40.25, 30.5, 25567.5);
+        assert!(upgrade_profile(&mut document).unwrap());
+        let profile: DataSchema = bson::from_document(document).unwrap();
+
+        assert_eq!(profile.schema_version, SCHEMA_VERSION);
+        assert_eq!(profile._id, "4902911_001");
+        assert_eq!(profile.geolocation.unwrap().coordinates, [-40.25, 30.5]);
+        assert!(!profile.position_missing);
+        assert!(!profile.time_missing);
+        assert_eq!(profile.CONFIG_MISSION_NUMBER, None);
+        // 25567.5 days after 1950-01-01 is 2020-01-01T12:00:00Z
+        assert_eq!(profile.timestamp, Some(bson::DateTime::from_millis(1_577_880_000_000)));
+        assert_eq!(profile.update_timestamp, bson::DateTime::parse_rfc3339_str("2020-01-02T13:04:05Z").ok());
+        let realtime_data = profile.realtime_data.unwrap();
+        assert_eq!(realtime_data["PRES"], vec![Some(5.0), Some(10.0), None]);
+        assert_eq!(realtime_data["TEMP"], vec![Some(20.5), None, None]);
+        assert_eq!(profile.level_qc.unwrap()["TEMP"], vec![QcFlag::Good, QcFlag::Missing, QcFlag::Blank]);
+    }
+
+    #[test]
+    fn file_stem_ids_are_collapsed() {
+        for (legacy_id, id) in [("D4902911_001", "4902911_001"), ("BR6901580_012_1", "B6901580_012_1"), ("4902911_002", "4902911_002")] {
+            let mut document = version_1_profile(-40.25, 30.5, 25567.5);
+            document.insert("_id", legacy_id);
+            upgrade_profile(&mut document).unwrap();
+            assert_eq!(document.get_str("_id").unwrap(), id);
+        }
+    }
+
+    #[test]
+    fn south_pole_placeholder_is_a_missing_position() {
+        let mut document = version_1_profile(0.0, -90.0, 999999.0);
+        upgrade_profile(&mut document).unwrap();
+        let profile: DataSchema = bson::from_document(document).unwrap();
+
+        assert!(profile.geolocation.is_none());
+        assert!(profile.position_missing);
+        assert!(profile.time_missing);
+        assert_eq!(profile.JULD, None);
+        assert_eq!(profile.timestamp, None);
+    }
+
+    #[test]
+    fn current_documents_are_left_alone() {
+        let mut document = version_1_profile(-40.25, 30.5, 25567.5);
+        upgrade_profile(&mut document).unwrap();
+        let upgraded = document.clone();
+        assert!(!upgrade_profile(&mut document).unwrap());
+        assert_eq!(document, upgraded);
+
+        document.insert("schema_version", SCHEMA_VERSION + 1);
+        assert!(upgrade_profile(&mut document).is_err());
+    }
+}
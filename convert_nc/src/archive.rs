@@ -0,0 +1,104 @@
+// reading profiles straight out of .tar / .tar.gz snapshots and .nc.gz files, without unpacking them to disk
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::{BufReader, Read};
+
+use flate2::read::GzDecoder;
+use tokio::sync::mpsc::Sender;
+
+use crate::{pair_files, ProfileInput};
+
+pub fn is_archive(path: &str) -> bool {
+    path.ends_with(".tar") || path.ends_with(".tar.gz") || path.ends_with(".tgz")
+}
+
+pub fn is_gzip(file_name: &str) -> bool {
+    file_name.ends_with(".gz")
+}
+
+// gunzips .gz contents, and passes anything else through untouched
+pub fn decompress<'a>(file_name: &str, contents: &'a [u8]) -> Result<Cow<'a, [u8]>, Box<dyn Error>> {
+    if !is_gzip(file_name) {
+        return Ok(Cow::Borrowed(contents));
+    }
+    let mut decompressed: Vec<u8> = Vec::new();
+    GzDecoder::new(contents).read_to_end(&mut decompressed)?;
+    Ok(Cow::Owned(decompressed))
+}
+
+fn open_archive(archive_path: &str) -> Result<tar::Archive<Box<dyn Read>>, std::io::Error> {
+    let file = BufReader::new(fs::File::open(archive_path)?);
+    let reader: Box<dyn Read> = if is_gzip(archive_path) || archive_path.ends_with(".tgz") {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+// members are named <archive_path>/<path in archive>, so the rest of the converter can treat them like files
+fn member_name(archive_path: &str, entry: &tar::Entry<Box<dyn Read>>) -> Result<Option<String>, std::io::Error> {
+    if !entry.header().entry_type().is_file() {
+        return Ok(None);
+    }
+    let path = entry.path()?.to_string_lossy().into_owned();
+    // profiles sit at .../<wmo>/profiles/<file>.nc, the same layout as a mirror on disk
+    let is_profile = path.rsplit('/').nth(1) == Some("profiles") && (path.ends_with(".nc") || path.ends_with(".nc.gz"));
+    Ok(if is_profile { Some(format!("{}/{}", archive_path, path)) } else { None })
+}
+
+// the profile files in an archive, for reconcile; for a .tar.gz this reads the whole archive, but only the headers are kept
+pub fn list_members(archive_path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut archive = open_archive(archive_path)?;
+    let mut members: Vec<String> = Vec::new();
+    for entry in archive.entries()? {
+        if let Some(name) = member_name(archive_path, &entry?)? {
+            members.push(name);
+        }
+    }
+    Ok(members)
+}
+
+// reads the archive once, pairing members as they arrive: a snapshot stores each float's profiles directory
+// together, so when the next directory starts the last one is complete, and is paired the same way as files on disk.
+// only one float's files are held in memory; a float whose files are split across the archive is paired part by part
+pub fn stream_pairs(archive_path: &str, sender: Sender<(ProfileInput, Option<ProfileInput>)>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut directory = String::new();
+    let mut held: HashMap<String, Vec<u8>> = HashMap::new();
+
+    let mut archive = open_archive(archive_path)?;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let Some(name) = member_name(archive_path, &entry)? else { continue };
+        let member_directory = name.rsplit_once('/').map_or("", |(directory, _)| directory);
+        if member_directory != directory {
+            if !send_pairs(&mut held, &sender) {
+                return Ok(());
+            }
+            directory = member_directory.to_string();
+        }
+        let mut contents: Vec<u8> = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut contents)?;
+        held.insert(name, contents);
+    }
+    send_pairs(&mut held, &sender);
+    Ok(())
+}
+
+// false once the converter has stopped taking files
+fn send_pairs(held: &mut HashMap<String, Vec<u8>>, sender: &Sender<(ProfileInput, Option<ProfileInput>)>) -> bool {
+    for (file_name, bgc_file_name) in pair_files(held.keys().cloned().collect()) {
+        let contents = held.remove(&file_name);
+        let input = ProfileInput { name: file_name, contents };
+        let bgc_input = bgc_file_name.map(|name| ProfileInput { contents: held.remove(&name), name });
+        if sender.blocking_send((input, bgc_input)).is_err() {
+            return false;
+        }
+    }
+    // whatever's left was passed over, like an R file superseded by its D file
+    held.clear();
+    true
+}
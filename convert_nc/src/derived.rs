@@ -0,0 +1,90 @@
+// TEOS-10 variables computed from each profile's PRES, TEMP and PSAL at ingest, stored alongside the measured ones;
+// they aren't added to STATION_PARAMETERS, which lists what the float measured
+
+use std::collections::HashMap;
+
+use argo_schema::{DataInfo, DataSchema, QcFlag};
+
+use crate::gsw;
+
+// (key, units, long_name)
+const DERIVED: [(&str, &str, &str); 4] = [
+    ("SA", "g/kg", "Absolute Salinity"),
+    ("CT", "degree_Celsius", "Conservative Temperature"),
+    ("SIGMA0", "kg/m^3", "Potential density anomaly with reference pressure of 0 dbar"),
+    ("DEPTH", "m", "Depth below the sea surface"),
+];
+
+type Levels = HashMap<String, Vec<Option<f64>>>;
+type LevelQc = HashMap<String, Vec<QcFlag>>;
+// one input's values and flags
+type Input<'a> = (&'a [Option<f64>], &'a [QcFlag]);
+// key, values, flags
+type Derived = (&'static str, Vec<Option<f64>>, Vec<QcFlag>);
+
+// the inputs' values and flags, or None if the profile doesn't have all three
+fn inputs<'a>(data: &'a Option<Levels>, qc: &'a Option<LevelQc>) -> Option<[Input<'a>; 3]> {
+    let (data, qc) = (data.as_ref()?, qc.as_ref()?);
+    let input = |param: &str| -> Option<Input<'a>> {
+        let values = data.get(param).filter(|values| !values.is_empty())?;
+        Some((values.as_slice(), qc.get(param).map(|flags| flags.as_slice()).unwrap_or(&[])))
+    };
+    Some([input("PRES")?, input("TEMP")?, input("PSAL")?])
+}
+
+// one set of levels, realtime or adjusted; each derived flag is the worst among the flags of what went into it
+fn derive(inputs: [Input; 3], longitude: f64, latitude: f64, position_qc: QcFlag, atlas: &gsw::SaarAtlas) -> Vec<Derived> {
+    let [(pres, pres_qc), (temp, temp_qc), (psal, psal_qc)] = inputs;
+    let flag = |flags: &[QcFlag], level: usize| flags.get(level).copied().unwrap_or(QcFlag::Blank);
+
+    let mut derived: Vec<Derived> = DERIVED.iter().map(|(key, _, _)| (*key, Vec::new(), Vec::new())).collect();
+    for (level, &p) in pres.iter().enumerate() {
+        let (t, sp) = (temp.get(level).copied().flatten(), psal.get(level).copied().flatten());
+        let sa = sp.zip(p).and_then(|(sp, p)| gsw::sa_from_sp(sp, p, longitude, latitude, atlas));
+        let ct = sa.zip(t).zip(p).map(|((sa, t), p)| gsw::ct_from_t(sa, t, p));
+        let sigma0 = sa.zip(ct).map(|(sa, ct)| gsw::sigma0(sa, ct));
+        let depth = p.map(|p| -gsw::z_from_p(p, latitude));
+
+        let (p_qc, t_qc, sp_qc) = (flag(pres_qc, level), flag(temp_qc, level), flag(psal_qc, level));
+        let sa_qc = QcFlag::worst([p_qc, sp_qc, position_qc]);
+        let ct_qc = QcFlag::worst([p_qc, t_qc, sp_qc, position_qc]);
+        let depth_qc = QcFlag::worst([p_qc, position_qc]);
+
+        for ((_, values, flags), (value, qc)) in derived.iter_mut().zip([(sa, sa_qc), (ct, ct_qc), (sigma0, ct_qc), (depth, depth_qc)]) {
+            values.push(value.filter(|value| value.is_finite()));
+            flags.push(qc);
+        }
+    }
+    derived
+}
+
+// needs a position, since Absolute Salinity and depth depend on it; keys the file already has are left alone
+pub fn add_derived_variables(data_object: &mut DataSchema, atlas: &gsw::SaarAtlas) {
+    let Some(geolocation) = &data_object.geolocation else { return };
+    let [longitude, latitude] = geolocation.coordinates;
+    let position_qc = data_object.POSITION_QC;
+
+    let realtime = inputs(&data_object.realtime_data, &data_object.level_qc).map(|inputs| derive(inputs, longitude, latitude, position_qc, atlas));
+    let adjusted = inputs(&data_object.adjusted_data, &data_object.adjusted_level_qc).map(|inputs| derive(inputs, longitude, latitude, position_qc, atlas));
+    if realtime.is_none() && adjusted.is_none() {
+        return;
+    }
+
+    let is_new = |key: &str| !data_object.STATION_PARAMETERS.iter().any(|param| param == key);
+    let new_keys: Vec<&str> = DERIVED.iter().map(|(key, _, _)| *key).filter(|key| is_new(key)).collect();
+    for (derived, data, qc) in [(realtime, &mut data_object.realtime_data, &mut data_object.level_qc), (adjusted, &mut data_object.adjusted_data, &mut data_object.adjusted_level_qc)] {
+        for (key, values, flags) in derived.into_iter().flatten().filter(|(key, _, _)| new_keys.contains(key)) {
+            data.get_or_insert_with(HashMap::new).insert(key.to_string(), values);
+            qc.get_or_insert_with(HashMap::new).insert(key.to_string(), flags);
+        }
+    }
+    let data_info = data_object.data_info.get_or_insert_with(HashMap::new);
+    for (key, units, long_name) in DERIVED.iter().filter(|(key, _, _)| new_keys.contains(key)) {
+        data_info.insert(key.to_string(), DataInfo {
+            DATA_MODE: data_object.DATA_MODE,
+            UNITS: units.to_string(),
+            LONG_NAME: long_name.to_string(),
+            PROFILE_PARAMETER_QC: "".to_string(),
+        });
+    }
+}
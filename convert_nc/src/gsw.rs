@@ -0,0 +1,475 @@
+// the parts of the Gibbs SeaWater (GSW) Oceanographic Toolbox of TEOS-10 needed for the derived variables,
+// ported from GSW-C; p is sea pressure in dbar, t and CT in degrees C, SP unitless, SA in g/kg
+
+use std::error::Error;
+
+use crate::unpack_optional_values;
+
+// Standard Ocean Reference Salinity, g/kg
+const SSO: f64 = 35.16504;
+// SSO / 35, the ratio of Reference Salinity to Practical Salinity
+const UPS: f64 = SSO / 35.0;
+const SFAC: f64 = 0.0248826675584615;
+const OFFSET: f64 = 5.971840214030754e-1;
+const CP0: f64 = 3991.86795711963;
+const T0: f64 = 273.15;
+const GAMMA: f64 = 2.26e-7;
+const DB2PA: f64 = 1e4;
+
+// Absolute Salinity from Practical Salinity: Reference Salinity scaled by the Absolute Salinity Anomaly Ratio
+// the atlas gives for the place and depth, or GSW's own relation in the Baltic. None where the atlas has no value
+pub fn sa_from_sp(sp: f64, p: f64, lon: f64, lat: f64, atlas: &SaarAtlas) -> Option<f64> {
+    if let Some(sa) = sa_from_sp_baltic(sp, lon, lat) {
+        return Some(sa);
+    }
+    atlas.saar(p, lon, lat).map(|saar| UPS * sp * (1.0 + saar))
+}
+
+fn xinterp1(x: &[f64], y: &[f64], x0: f64) -> f64 {
+    let k = x.windows(2).position(|w| x0 <= w[1]).unwrap_or(x.len() - 2);
+    y[k] + (y[k + 1] - y[k]) * (x0 - x[k]) / (x[k + 1] - x[k])
+}
+
+fn sa_from_sp_baltic(sp: f64, lon: f64, lat: f64) -> Option<f64> {
+    let (xb_left, yb_left) = ([12.6, 7.0, 26.0], [50.0, 59.0, 69.0]);
+    let (xb_right, yb_right) = ([45.0, 26.0], [50.0, 69.0]);
+    let lon = if lon < 0.0 { lon + 360.0 } else { lon };
+    if xb_left[1] < lon && lon < xb_right[0] && yb_left[0] < lat && lat < yb_left[2] {
+        let xx_left = xinterp1(&yb_left, &xb_left, lat);
+        let xx_right = xinterp1(&yb_right, &xb_right, lat);
+        if xx_left <= lon && lon <= xx_right {
+            return Some(((SSO - 0.087) / 35.0) * sp + 0.087);
+        }
+    }
+    None
+}
+
+// the Absolute Salinity Anomaly Ratio atlas, from GSW's gsw_data_v3_0.nc, which GSW-C compiles in as gsw_saar_data.c;
+// gridded every 4 degrees at standard pressures, and held in GSW-C's order, longitude slowest and pressure fastest.
+// missing values are NaN
+pub struct SaarAtlas {
+    p_ref: Vec<f64>,
+    lats_ref: Vec<f64>,
+    longs_ref: Vec<f64>,
+    // the number of standard pressures with data at each grid point, [longitude][latitude]
+    ndepth_ref: Vec<f64>,
+    // [longitude][latitude][pressure]
+    saar_ref: Vec<f64>,
+}
+
+// the Panama barrier: the Pacific and the Caribbean are interpolated separately
+const LONGS_PAN: [f64; 6] = [260.00, 272.59, 276.50, 278.65, 280.73, 292.0];
+const LATS_PAN: [f64; 6] = [19.55, 13.97, 9.60, 8.10, 9.33, 3.4];
+
+// the grid corners around a point, as offsets in longitude and latitude, anticlockwise from the south-west
+const DELI: [usize; 4] = [0, 1, 1, 0];
+const DELJ: [usize; 4] = [0, 0, 1, 1];
+
+impl SaarAtlas {
+    // the atlas variables may be stored in either dimension order; a grid of any other shape is an error
+    pub fn open(file_name: &str) -> Result<SaarAtlas, Box<dyn Error>> {
+        let file = netcdf::open(file_name)?;
+        let values = |name: &str| -> Result<(Vec<f64>, Vec<usize>), Box<dyn Error>> {
+            let variable = file.variable(name).ok_or(format!("{} has no {} variable", file_name, name))?;
+            let shape: Vec<usize> = variable.dimensions().iter().map(|d| d.len()).collect();
+            let values = unpack_optional_values(&variable, (..).into())?.into_iter().map(|value| value.unwrap_or(f64::NAN)).collect();
+            Ok((values, shape))
+        };
+        let (p_ref, _) = values("p_ref")?;
+        let (lats_ref, _) = values("lats_ref")?;
+        let (longs_ref, _) = values("longs_ref")?;
+        let (nx, ny, nz) = (longs_ref.len(), lats_ref.len(), p_ref.len());
+        if nx < 2 || ny < 2 || nz < 2 {
+            return Err(format!("{} doesn't hold a SAAR grid", file_name).into());
+        }
+
+        let (ndepth, shape) = values("ndepth_ref")?;
+        let ndepth_ref = if shape == [nx, ny] {
+            ndepth
+        } else if shape == [ny, nx] {
+            (0..nx * ny).map(|i| ndepth[i / ny + nx * (i % ny)]).collect()
+        } else {
+            return Err(format!("ndepth_ref in {} is {:?}, expected {:?}", file_name, shape, [nx, ny]).into());
+        };
+        let (saar, shape) = values("SAAR_ref")?;
+        let saar_ref = if shape == [nx, ny, nz] {
+            saar
+        } else if shape == [nz, ny, nx] {
+            (0..nx * ny * nz).map(|i| {
+                let (ix, iy, iz) = (i / (ny * nz), (i / nz) % ny, i % nz);
+                saar[ix + nx * (iy + ny * iz)]
+            }).collect()
+        } else {
+            return Err(format!("SAAR_ref in {} is {:?}, expected {:?}", file_name, shape, [nx, ny, nz]).into());
+        };
+        Ok(SaarAtlas { p_ref, lats_ref, longs_ref, ndepth_ref, saar_ref })
+    }
+
+    // gsw_saar: the ratio, interpolated horizontally from the four grid points around the place and then in pressure;
+    // 0 far from the ocean, None outside the atlas
+    pub fn saar(&self, p: f64, lon: f64, lat: f64) -> Option<f64> {
+        let (ny, nz) = (self.lats_ref.len(), self.p_ref.len());
+        if p.is_nan() || lon.is_nan() || !(-86.0..=90.0).contains(&lat) {
+            return None;
+        }
+        let lon = if lon < 0.0 { lon + 360.0 } else { lon };
+        let (longs_ref, lats_ref, p_ref) = (&self.longs_ref, &self.lats_ref, &self.p_ref);
+        let dlong = longs_ref[1] - longs_ref[0];
+        let dlat = lats_ref[1] - lats_ref[0];
+
+        let grid_index = |x: f64, grid: &[f64]| -> Option<usize> {
+            let n = grid.len();
+            let i = ((n - 1) as f64 * (x - grid[0]) / (grid[n - 1] - grid[0])).floor();
+            (0.0..n as f64).contains(&i).then(|| (i as usize).min(n - 2))
+        };
+        let indx0 = grid_index(lon, longs_ref)?;
+        let indy0 = grid_index(lat, lats_ref)?;
+
+        // the deepest standard pressure with data at any of the four corners
+        let ndepth_max = (0..4)
+            .map(|k| self.ndepth_ref[indy0 + DELJ[k] + (indx0 + DELI[k]) * ny])
+            .filter(|ndepth| *ndepth > 0.0 && ndepth.is_finite())
+            .fold(None, |max: Option<f64>, ndepth| Some(max.map_or(ndepth, |max| max.max(ndepth))));
+        let Some(ndepth_max) = ndepth_max else { return Some(0.0) };
+
+        let p = p.min(p_ref[ndepth_max as usize - 1]);
+        let indz0 = util_indx(p_ref, p);
+
+        let r1 = (lon - longs_ref[indx0]) / (longs_ref[indx0 + 1] - longs_ref[indx0]);
+        let s1 = (lat - lats_ref[indy0]) / (lats_ref[indy0 + 1] - lats_ref[indy0]);
+        let t1 = (p - p_ref[indz0]) / (p_ref[indz0 + 1] - p_ref[indz0]);
+
+        let in_panama = (LONGS_PAN[0]..=LONGS_PAN[5] - 0.001).contains(&lon) && (LATS_PAN[5]..=LATS_PAN[0]).contains(&lat);
+        let horizontal = |iz: usize| -> f64 {
+            let mut saar = [0.0; 4];
+            for k in 0..4 {
+                saar[k] = self.saar_ref[iz + nz * (indy0 + DELJ[k] + (indx0 + DELI[k]) * ny)];
+            }
+            if in_panama {
+                saar = add_barrier(saar, lon, lat, longs_ref[indx0], lats_ref[indy0], dlong, dlat);
+            } else if saar.iter().any(|value| !value.is_finite()) {
+                saar = add_mean(saar);
+            }
+            (1.0 - s1) * (saar[0] + r1 * (saar[1] - saar[0])) + s1 * (saar[3] + r1 * (saar[2] - saar[3]))
+        };
+        let sa_upper = horizontal(indz0);
+        let sa_lower = Some(horizontal(indz0 + 1)).filter(|value| value.is_finite()).unwrap_or(sa_upper);
+        Some(sa_upper + t1 * (sa_lower - sa_upper)).filter(|value| value.is_finite())
+    }
+}
+
+// the k with x[k] <= z < x[k + 1], clamped to the ends of x
+fn util_indx(x: &[f64], z: f64) -> usize {
+    let n = x.len();
+    if z > x[0] && z < x[n - 1] {
+        let (mut kl, mut ku) = (0, n - 1);
+        while ku - kl > 1 {
+            let km = (ku + kl) / 2;
+            if z > x[km] {
+                kl = km;
+            } else {
+                ku = km;
+            }
+        }
+        if z == x[kl + 1] { kl + 1 } else { kl }
+    } else if z <= x[0] {
+        0
+    } else {
+        n - 2
+    }
+}
+
+// missing corners take the mean of the others
+fn add_mean(data: [f64; 4]) -> [f64; 4] {
+    let valid: Vec<f64> = data.iter().copied().filter(|value| value.abs() <= 100.0).collect();
+    let mean = if valid.is_empty() { 0.0 } else { valid.iter().sum::<f64>() / valid.len() as f64 };
+    data.map(|value| if value.abs() < 100.0 { value } else { mean })
+}
+
+// corners on the other side of the Panama barrier from the point, or missing, take the mean of those on its side
+fn add_barrier(data: [f64; 4], lon: f64, lat: f64, long_grid: f64, lat_grid: f64, dlong_grid: f64, dlat_grid: f64) -> [f64; 4] {
+    let line_latitude = |lon: f64| {
+        let k = util_indx(&LONGS_PAN, lon);
+        let r = (lon - LONGS_PAN[k]) / (LONGS_PAN[k + 1] - LONGS_PAN[k]);
+        LATS_PAN[k] + r * (LATS_PAN[k + 1] - LATS_PAN[k])
+    };
+    let above_line0 = line_latitude(lon) <= lat;
+    let (west, east) = (line_latitude(long_grid), line_latitude(long_grid + dlong_grid));
+    let above_line = [west <= lat_grid, east <= lat_grid, east <= lat_grid + dlat_grid, west <= lat_grid + dlat_grid];
+
+    let same_side: Vec<f64> = (0..4).filter(|&k| data[k].abs() <= 100.0 && above_line[k] == above_line0).map(|k| data[k]).collect();
+    let mean = if same_side.is_empty() { 0.0 } else { same_side.iter().sum::<f64>() / same_side.len() as f64 };
+    let mut output = data;
+    for k in 0..4 {
+        if !data[k].is_finite() || above_line[k] != above_line0 {
+            output[k] = mean;
+        }
+    }
+    output
+}
+
+// Conservative Temperature from in-situ temperature
+pub fn ct_from_t(sa: f64, t: f64, p: f64) -> f64 {
+    ct_from_pt(sa, pt0_from_t(sa, t, p))
+}
+
+pub fn ct_from_pt(sa: f64, pt: f64) -> f64 {
+    let x2 = SFAC * sa;
+    let x = x2.sqrt();
+    let y = pt * 0.025;
+
+    let pot_enthalpy = 61.01362420681071 + 168776.46138048015 * y
+        + -2735.2785605119625 * y * y + 2574.2164453821433 * y * y * y
+        + -1536.6644434977543 * y * y * y * y + 545.7340497931629 * y * y * y * y * y
+        + (-50.91091728474331 - 18.30489878927802 * y) * y * y * y * y * y * y
+        + x2 * (268.5520265845071 + -12019.028203559312 * y
+            + 3734.858026725145 * y * y + -2046.7671145057618 * y * y * y
+            + 465.28655623826234 * y * y * y * y + -0.6370820302376359 * y * y * y * y * y
+            + -10.650848542359153 * y * y * y * y * y * y
+            + x * (937.2099110620707 + 588.1802812170108 * y
+                + 248.39476522971285 * y * y + -3.871557904936333 * y * y * y
+                + -2.6268019854268356 * y * y * y * y
+                + x * (-1687.914374187449 + 936.3206544460336 * y
+                    + -942.7827304544439 * y * y + 369.4389437509002 * y * y * y
+                    + -33.83664947895248 * y * y * y * y + -9.987880382780322 * y * y * y * y * y
+                    + x * (246.9598888781377 + x * (123.59576582457964 - 48.5891069025409 * x)))));
+
+    pot_enthalpy / CP0
+}
+
+// potential temperature referenced to 0 dbar, by matching entropy
+pub fn pt0_from_t(sa: f64, t: f64, p: f64) -> f64 {
+    let s1 = sa / UPS;
+    let mut pt0 = t + p * (8.65483913395442e-6 - s1 * 1.41636299744881e-6 - p * 7.38286467135737e-9
+        + t * (-8.38241357039698e-6 + s1 * 2.83933368585534e-8 + t * 1.77803965218656e-8 + p * 1.71155619208233e-10));
+
+    let mut dentropy_dt = CP0 / ((T0 + pt0) * (1.0 - 0.05 * (1.0 - sa / SSO)));
+    let true_entropy_part = entropy_part(sa, t, p);
+    for _ in 0..2 {
+        let pt0_old = pt0;
+        let dentropy = entropy_part(sa, pt0_old, 0.0) - true_entropy_part;
+        pt0 = pt0_old - dentropy / dentropy_dt;
+        let pt0m = 0.5 * (pt0 + pt0_old);
+        dentropy_dt = -gibbs_pt0_pt0(sa, pt0m);
+        pt0 = pt0_old - dentropy / dentropy_dt;
+    }
+    pt0
+}
+
+// entropy minus the terms that are a function of SA only
+fn entropy_part(sa: f64, t: f64, p: f64) -> f64 {
+    let x2 = SFAC * sa;
+    let x = x2.sqrt();
+    let y = t * 0.025;
+    let z = p * 1e-4;
+
+    let g03 = z * (-270.983805184062
+        + z * (776.153611613101 + z * (-196.51255088122 + (28.9796526294175 - 2.13290083518327 * z) * z)))
+        + y * (-24715.571866078 + z * (2910.0729080936
+            + z * (-1513.116771538718 + z * (546.959324647056 + z * (-111.1208127634436 + 8.68841343834394 * z))))
+        + y * (2210.2236124548363 + z * (-2017.52334943521
+            + z * (1498.081172457456 + z * (-718.6359919632359 + (146.4037555781616 - 4.9892131862671505 * z) * z)))
+        + y * (-592.743745734632 + z * (1591.873781627888
+            + z * (-1207.261522487504 + (608.785486935364 - 105.4993508931208 * z) * z))
+        + y * (290.12956292128547 + z * (-973.091553087975
+            + z * (602.603274510125 + z * (-276.361526170076 + 32.40953340386105 * z)))
+        + y * (-113.90630790850321 + y * (21.35571525415769 - 67.41756835751434 * z)
+            + z * (381.06836198507096 + z * (-133.7383902842754 + 49.023632509086724 * z)))))));
+
+    let g08 = x2 * (z * (729.116529735046
+        + z * (-343.956902961561 + z * (124.687671116248 + z * (-31.656964386073 + 7.04658803315449 * z))))
+        + x * (x * (y * (-137.1145018408982 + y * (148.10030845687618 + y * (-68.5590309679152 + 12.4848504784754 * y)))
+            - 22.6683558512829 * z)
+            + z * (-175.292041186547 + (83.1923927801819 - 29.483064349429 * z) * z)
+            + y * (-86.1329351956084 + z * (766.116132004952 + z * (-108.3834525034224 + 51.2796974779828 * z))
+                + y * (-30.0682112585625 - 1380.9597954037708 * z + y * (3.50240264723578 + 938.26075044542 * z))))
+        + y * (1760.062705994408 + y * (-675.802947790203
+            + y * (365.7041791005036 + y * (-108.30162043765552 + 12.78101825083098 * y)
+                + z * (-1190.914967948748 + (298.904564555024 - 145.9491676006352 * z) * z))
+            + z * (2082.7344423998043 + z * (-614.668925894709 + (340.685093521782 - 33.3848202979239 * z) * z)))
+        + z * (-1721.528607567954 + z * (674.819060538734
+            + z * (-356.629112415276 + (88.4080716616 - 15.84003094423364 * z) * z)))));
+
+    -(g03 + g08) * 0.025
+}
+
+// the second derivative of the Gibbs function with respect to temperature, at p = 0
+fn gibbs_pt0_pt0(sa: f64, pt0: f64) -> f64 {
+    let x2 = SFAC * sa;
+    let x = x2.sqrt();
+    let y = pt0 * 0.025;
+
+    let g03 = -24715.571866078 + y * (4420.4472249096725 + y * (-1778.231237203896
+        + y * (1160.5182516851419 + y * (-569.531539542516 + y * 128.13429152494615))));
+
+    let g08 = x2 * (1760.062705994408 + x * (-86.1329351956084
+        + x * (-137.1145018408982 + y * (296.20061691375236 + y * (-205.67709290374563 + 49.9394019139016 * y)))
+        + y * (-60.136422517125 + y * 10.50720794170734))
+        + y * (-1351.605895580406 + y * (1097.1125373015109 + y * (-433.20648175062206 + 63.905091254154904 * y))));
+
+    (g03 + g08) * 0.000625
+}
+
+// potential density anomaly referenced to 0 dbar, kg/m3, from the 75-term expression for specific volume
+pub fn sigma0(sa: f64, ct: f64) -> f64 {
+    let xs = (SFAC * sa + OFFSET).sqrt();
+    let ys = ct * 0.025;
+
+    let vp0 = 1.0769995862e-3 + xs * (-3.1038981976e-4 + xs * (6.6928067038e-4 + xs * (-8.5047933937e-4
+            + xs * (5.8086069943e-4 + xs * (-2.1092370507e-4 + xs * 3.1932457305e-5)))))
+        + ys * (-1.5649734675e-5 + xs * (3.5009599764e-5 + xs * (-4.3592678561e-5 + xs * (3.4532461828e-5
+                + xs * (-1.1959409788e-5 + xs * 1.3864594581e-6))))
+            + ys * (2.7762106484e-5 + xs * (-3.7435842344e-5 + xs * (3.5907822760e-5
+                    + xs * (-1.8698584187e-5 + xs * 3.8595339244e-6)))
+                + ys * (-1.6521159259e-5 + xs * (2.4141479483e-5 + xs * (-1.4353633048e-5 + xs * 2.2863324556e-6))
+                    + ys * (6.9111322702e-6 + xs * (-8.7595873154e-6 + xs * 4.3703680598e-6)
+                        + ys * (-8.0539615540e-7 + xs * -3.3052758900e-7 + ys * 2.0543094268e-7)))));
+
+    1.0 / vp0 - 1000.0
+}
+
+// height from sea pressure, negative below the sea surface
+pub fn z_from_p(p: f64, lat: f64) -> f64 {
+    let x = lat.to_radians().sin();
+    let sin2 = x * x;
+    let b = 9.780327 * (1.0 + (5.2792e-3 + (2.32e-5 * sin2)) * sin2);
+    let a = -0.5 * GAMMA * b;
+    let c = enthalpy_sso_0(p);
+    -2.0 * c / (b + (b * b - 4.0 * a * c).sqrt())
+}
+
+// dynamic enthalpy of SSO, 0 degrees C seawater
+fn enthalpy_sso_0(p: f64) -> f64 {
+    let z = p * 1e-4;
+    let dynamic_enthalpy_sso_0_p = z * (9.72661385484387e-4 + z * (-2.252956605630465e-5
+        + z * (2.376909655387404e-6 + z * (-1.664294869986011e-7
+        + z * (-5.988108894465758e-9 + z * (-2.1078768810e-9 + 2.8019291329e-10 * z))))));
+    dynamic_enthalpy_sso_0_p * DB2PA * 1e4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the check values from GSW-C's gsw_check_data
+    const SA: [f64; 6] = [34.7118, 34.8915, 35.0256, 34.8472, 34.7366, 34.7324];
+    const T: [f64; 6] = [28.7856, 28.4329, 22.8103, 10.2600, 6.8863, 4.4036];
+    const P: [f64; 6] = [10.0, 50.0, 125.0, 250.0, 600.0, 1000.0];
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() < tolerance, "{} isn't within {} of {}", actual, tolerance, expected);
+    }
+
+    #[test]
+    fn potential_temperature() {
+        let expected = [28.783196819670632, 28.420983342398962, 22.784930399117108, 10.230523661095731, 6.829230224409661, 4.324510571845719];
+        for i in 0..6 {
+            assert_close(pt0_from_t(SA[i], T[i], P[i]), expected[i], 1e-10);
+        }
+    }
+
+    #[test]
+    fn conservative_temperature() {
+        let expected = [28.809919826700281, 28.439227816091140, 22.786176893078498, 10.226189266620782, 6.827213633479988, 4.323575748610455];
+        for i in 0..6 {
+            assert_close(ct_from_t(SA[i], T[i], P[i]), expected[i], 1e-10);
+        }
+    }
+
+    #[test]
+    fn potential_density_anomaly() {
+        let ct = [28.8099, 28.4392, 22.7862, 10.2262, 6.8272];
+        let expected = [21.797900819337656, 22.052215404397316, 23.892985307893923, 26.667608665972011, 27.107380455119710];
+        for i in 0..5 {
+            assert_close(sigma0(SA[i], ct[i]), expected[i], 1e-10);
+        }
+    }
+
+    #[test]
+    fn height_from_pressure() {
+        let expected = [-9.9445834469453, -49.7180897012550, -124.2726219409978, -248.4700576548589, -595.8253480356214, -992.0919060719987];
+        for i in 0..6 {
+            assert_close(z_from_p(P[i], 4.0), expected[i], 1e-10);
+        }
+    }
+
+    #[test]
+    fn baltic_absolute_salinity() {
+        let sp = [6.5683, 6.6719, 6.8108, 7.2629, 7.4825, 10.2796];
+        let expected = [6.6699, 6.7738, 6.9130, 7.3661, 7.5862, 10.3895];
+        for i in 0..6 {
+            assert_close(sa_from_sp_baltic(sp[i], 20.0, 59.0).unwrap(), expected[i], 1e-4);
+        }
+        assert_eq!(sa_from_sp_baltic(35.0, 188.0, 4.0), None);
+    }
+
+    // a 3 x 2 grid with two standard pressures, where the ratio is linear in position and pressure
+    fn small_atlas(saar: impl Fn(f64, f64, f64) -> f64) -> SaarAtlas {
+        let (longs_ref, lats_ref, p_ref) = (vec![0.0, 4.0, 8.0], vec![0.0, 4.0], vec![0.0, 1000.0]);
+        let mut saar_ref = vec![];
+        for lon in &longs_ref {
+            for lat in &lats_ref {
+                for p in &p_ref {
+                    saar_ref.push(saar(*lon, *lat, *p));
+                }
+            }
+        }
+        SaarAtlas { ndepth_ref: vec![2.0; 6], p_ref, lats_ref, longs_ref, saar_ref }
+    }
+
+    #[test]
+    fn saar_interpolation() {
+        let linear = |lon: f64, lat: f64, p: f64| 0.001 * lon + 0.0001 * lat + 0.000001 * p;
+        let atlas = small_atlas(linear);
+        for (p, lon, lat) in [(0.0, 0.0, 0.0), (250.0, 1.0, 3.0), (500.0, 6.5, 2.0), (1000.0, 8.0, 4.0)] {
+            assert_close(atlas.saar(p, lon, lat).unwrap(), linear(lon, lat, p), 1e-12);
+        }
+        // below the deepest standard pressure the ratio is held
+        assert_close(atlas.saar(3000.0, 1.0, 3.0).unwrap(), linear(1.0, 3.0, 1000.0), 1e-12);
+        assert_eq!(atlas.saar(f64::NAN, 1.0, 3.0), None);
+        assert_eq!(atlas.saar(10.0, 1.0, -87.0), None);
+    }
+
+    #[test]
+    fn saar_missing_corners() {
+        let mut atlas = small_atlas(|lon, _, _| if lon == 0.0 { f64::NAN } else { 0.002 });
+        // corners without data take the mean of the others
+        assert_close(atlas.saar(10.0, 1.0, 1.0).unwrap(), 0.002, 1e-12);
+        // and the ratio is 0 where there's no ocean
+        atlas.ndepth_ref = vec![f64::NAN; 6];
+        assert_eq!(atlas.saar(10.0, 1.0, 1.0), Some(0.0));
+    }
+
+    #[test]
+    fn saar_panama_barrier() {
+        // a cell astride the barrier, Pacific to the south and Caribbean to the north
+        let atlas = SaarAtlas {
+            p_ref: vec![0.0, 1000.0],
+            lats_ref: vec![8.0, 12.0],
+            longs_ref: vec![276.0, 280.0],
+            ndepth_ref: vec![2.0; 4],
+            saar_ref: vec![1.0, 1.0, 0.004, 0.004, 1.0, 1.0, 0.002, 0.002],
+        };
+        // the southern corners are across the barrier from the point, so they take the northern corners' mean
+        assert_close(atlas.saar(10.0, -83.0, 11.0).unwrap(), 0.003375, 1e-12);
+    }
+
+    #[test]
+    fn absolute_salinity() {
+        let atlas = small_atlas(|_, _, _| 0.0);
+        assert_close(sa_from_sp(35.0, 10.0, 1.0, 1.0, &atlas).unwrap(), SSO, 1e-12);
+        let atlas = small_atlas(|_, _, _| 0.001);
+        assert_close(sa_from_sp(35.0, 10.0, 1.0, 1.0, &atlas).unwrap(), SSO * 1.001, 1e-12);
+    }
+
+    // needs GSW's atlas: GSW_DATA=gsw_data_v3_0.nc cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn absolute_salinity_check_values() {
+        let atlas = SaarAtlas::open(&std::env::var("GSW_DATA").expect("GSW_DATA names gsw_data_v3_0.nc")).unwrap();
+        let sp = [34.5487, 34.7275, 34.8605, 34.6810, 34.5680, 34.5600];
+        for i in 0..6 {
+            assert_close(sa_from_sp(sp[i], P[i], 188.0, 4.0, &atlas).unwrap(), SA[i], 1e-4);
+        }
+    }
+}
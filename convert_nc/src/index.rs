@@ -0,0 +1,98 @@
+// reading the GDAC profile index files: ar_index_global_prof.txt, argo_bio-profile_index.txt
+// and argo_synthetic-profile_index.txt
+
+use std::error::Error;
+use std::fs;
+
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    pub file: String, // relative to <mirror>/dac
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub institution: String,
+    pub date_update: String,
+}
+
+pub fn read_index(index_file: &str) -> Result<Vec<IndexEntry>, Box<dyn Error>> {
+    parse_index(&fs::read_to_string(index_file)?)
+}
+
+fn parse_index(contents: &str) -> Result<Vec<IndexEntry>, Box<dyn Error>> {
+    let mut lines = contents.lines().filter(|line| !line.starts_with('#'));
+
+    // column order differs between the core and BGC indexes, so go by the header
+    let header: Vec<&str> = lines.next().ok_or("Empty index file")?.split(',').collect();
+    let column = |name: &str| header.iter().position(|h| h.trim() == name).ok_or(format!("Index has no {} column", name));
+    let (file, latitude, longitude, institution, date_update) = (
+        column("file")?,
+        column("latitude")?,
+        column("longitude")?,
+        column("institution")?,
+        column("date_update")?,
+    );
+
+    let mut entries: Vec<IndexEntry> = Vec::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        if fields.len() < header.len() {
+            continue;
+        }
+        entries.push(IndexEntry {
+            file: fields[file].to_string(),
+            latitude: fields[latitude].parse().ok(),
+            longitude: fields[longitude].parse().ok(),
+            institution: fields[institution].to_string(),
+            date_update: fields[date_update].to_string(),
+        });
+    }
+    Ok(entries)
+}
+
+pub fn resolve(mirror_root: &str, entry: &IndexEntry) -> String {
+    format!("{}/dac/{}", mirror_root, entry.file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn core_index() {
+        let contents = "\
+# Title : Profile directory file of the Argo Global Data Assembly Center
+# FTP root number 1 : ftp://ftp.ifremer.fr/ifremer/argo/dac
+file,date,latitude,longitude,ocean,profiler_type,institution,date_update
+aoml/13857/profiles/R13857_001.nc,19970729200300,0.267,-16.032,A,845,AO,20181011180520
+aoml/13857/profiles/R13857_002.nc,19970808
+aoml/13857/profiles/R13857_003.nc,19970818190700,,,A,845,AO,20181011180521
+";
+        let entries = parse_index(contents).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].file, "aoml/13857/profiles/R13857_001.nc");
+        assert_eq!(entries[0].latitude, Some(0.267));
+        assert_eq!(entries[0].longitude, Some(-16.032));
+        assert_eq!(entries[0].institution, "AO");
+        assert_eq!(entries[0].date_update, "20181011180520");
+        assert_eq!((entries[1].latitude, entries[1].longitude), (None, None));
+    }
+
+    #[test]
+    fn bio_index() {
+        let contents = "\
+# Title : Profile directory file of the Argo Global Data Assembly Center
+file,date,latitude,longitude,ocean,profiler_type,institution,parameters,parameter_data_mode,date_update
+coriolis/1900722/profiles/BD1900722_001.nc,20061022021500,-40.316,73.389,I,845,IF,PRES TEMP_DOXY DOXY,RRD,20181219162302
+";
+        let entries = parse_index(contents).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].file, "coriolis/1900722/profiles/BD1900722_001.nc");
+        assert_eq!(entries[0].institution, "IF");
+        assert_eq!(entries[0].date_update, "20181219162302");
+    }
+
+    #[test]
+    fn missing_columns() {
+        assert!(parse_index("file,date,latitude,longitude\n").is_err());
+        assert!(parse_index("# only comments\n").is_err());
+    }
+}
@@ -2,15 +2,63 @@
 use netcdf;
 use tokio;
 use std::error::Error;
-use std::env;
-use mongodb::bson::{doc};
-use mongodb::{Client, options::{ClientOptions, ResolverConfig}};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use mongodb::bson::{self, doc};
+use sha2::{Digest, Sha256};
+use chrono::{Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::task::JoinSet;
+use argo_schema::{collapse_profile_id, CalibrationRecord, DataInfo, DataMode, DataSchema, Direction, GeoJSONPoint, HistoryRecord, MetaSchema, Provenance, QcFlag, SourceFile, SCHEMA_VERSION};
+use argo_schema::migrate::{upgrade_meta, upgrade_profile};
+
+mod meta;
+mod traj;
+mod tech;
+mod index;
+mod sink;
+mod validate;
+mod archive;
+mod gsw;
+mod derived;
+
+use sink::Sink;
 
 // helper functions ///////////////////////////////////////////
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FileKind {
+    Core,      // R/D<wmo>_<cyc>.nc
+    Bgc,       // BR/BD<wmo>_<cyc>.nc, BGC parameters on the core file's levels
+    Synthetic, // S<wmo>_<cyc>.nc and <wmo>_Sprof.nc, BGC synthetic profiles
+}
+
+fn detect_file_kind(file_name: &str, DATA_TYPE: &str) -> FileKind {
+    let base = file_name.rsplit('/').next().unwrap_or("");
+    if base.starts_with('B') {
+        FileKind::Bgc
+    } else if base.starts_with('S') || base.trim_end_matches(".gz").ends_with("_Sprof.nc") || DATA_TYPE.to_lowercase().contains("synthetic") {
+        FileKind::Synthetic
+    } else {
+        FileKind::Core
+    }
+}
+
+fn profile_data_mode(PARAMETER_DATA_MODE: &[String]) -> String {
+    // a BGC profile is delayed mode as soon as any of its parameters is, and realtime only if all of them are
+    if PARAMETER_DATA_MODE.iter().any(|mode| mode == "D") {
+        "D".to_string()
+    } else if PARAMETER_DATA_MODE.iter().any(|mode| mode == "A") {
+        "A".to_string()
+    } else {
+        "R".to_string()
+    }
+}
+
 fn trim_null_bytes(input: String) -> String {
     input.trim().trim_end_matches('\0').to_string()
 }
@@ -45,240 +93,338 @@ fn unpack_string_array(name: &str, buflen: usize, arraydim: usize, extents: netc
     vec![String::new(); arraydim]
 }
 
+fn file_stem(file_name: &str) -> &str {
+    file_name
+        .rsplit('/')
+        .next()
+        .and_then(|name| name.strip_suffix(".nc.gz").or_else(|| name.strip_suffix(".nc")))
+        .unwrap_or("")
+}
+
+fn unpack_history_string(name: &str, hindex: usize, pindex: usize, file: &netcdf::File) -> String {
+    // history strings are dimensioned [N_HISTORY, N_PROF, STRINGx], where the string length varies between format versions
+    let strlen = file.variable(name)
+        .and_then(|variable| variable.dimensions().last().map(|d| d.len()))
+        .unwrap_or(0);
+    unpack_string(name, strlen, [hindex..hindex+1, pindex..pindex+1, 0..strlen].into(), file)
+}
+
+fn unpack_calib_string(name: &str, pindex: usize, cindex: usize, param_index: usize, file: &netcdf::File) -> String {
+    // calibration strings are dimensioned [N_PROF, N_CALIB, N_PARAM, STRINGx]
+    let strlen = file.variable(name)
+        .and_then(|variable| variable.dimensions().last().map(|d| d.len()))
+        .unwrap_or(0);
+    unpack_string(name, strlen, [pindex..pindex+1, cindex..cindex+1, param_index..param_index+1, 0..strlen].into(), file)
+}
+
+fn profile_id(stem: &str, pindex: usize) -> String {
+    // R and D files for the same cycle share one identity; B and S prefixes stay, since those documents are
+    // stored separately from the core profile
+    let id = collapse_profile_id(stem);
+
+    // the first profile gets the bare id; any additional profiles in a
+    // multi-profile file get their N_PROF index appended
+    if pindex == 0 {
+        id
+    } else {
+        format!("{}_{}", id, pindex)
+    }
+}
+
+fn base_id(id: &str) -> &str {
+    // <wmo>_<cyc>, without any N_PROF suffix
+    match id.match_indices('_').nth(1) {
+        Some((i, _)) => &id[..i],
+        None => id,
+    }
+}
+
 fn split_string(input: String, separator: char) -> Vec<String> {
     input.split(separator).map(|s| s.trim().to_string()).collect()
 }
 
-////////////////////////////////////////////////////////////////
+// YYYYMMDDHHMMSS, the format of REFERENCE_DATE_TIME and the DATE_ variables
+fn parse_argo_date(date: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(date.trim(), "%Y%m%d%H%M%S").ok()
+}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    
-    // Read the command line argument as data_directory
-    let data_directory = std::env::args().nth(1).expect("Missing data directory argument");
-
-    // mongodb setup ///////////////////////////////////////////
-    // Load the MongoDB connection string from an environment variable:
-    let client_uri =
-       env::var("MONGODB_URI").expect("You must set the MONGODB_URI environment var!"); 
-
-    // A Client is needed to connect to MongoDB:
-    // An extra line of code to work around a DNS issue on Windows:
-    let options =
-       ClientOptions::parse_with_resolver_config(&client_uri, ResolverConfig::cloudflare())
-          .await?;
-    let client = Client::with_options(options)?; 
-    let argo = client.database("argo").collection::<DataSchema>("argo");
-    let argo_meta = client.database("argo").collection::<MetaSchema>("argoMeta");
-
-    // structs to describe documents //////////////////////////////
-
-    #[derive(Serialize, Deserialize, Debug, Clone)]
-    struct GeoJSONPoint {
-        #[serde(rename = "type")]
-        location_type: String,
-        coordinates: [f64; 2],
-    } 
-
-    #[derive(Serialize, Deserialize, Debug, Clone)]
-    struct DataInfo {
-        DATA_MODE: String,
-        UNITS: String,
-        LONG_NAME: String,
-        PROFILE_PARAMETER_QC: String,
-    } 
-
-    #[derive(Serialize, Deserialize, Debug, Clone)]
-    struct DataSchema {
-        _id: String,
-        geolocation: GeoJSONPoint,
-        metadata: Vec<String>,
-        CYCLE_NUMBER: i32,
-        DIRECTION: String,
-        DATA_STATE_INDICATOR: String,
-        DATA_MODE: String,
-        DATE_CREATION: String,
-        DATE_UPDATE: String,
-        DC_REFERENCE: String,
-        JULD: f64,
-        JULD_QC: String,
-        JULD_LOCATION: f64,
-        POSITION_QC: String,
-        VERTICAL_SAMPLING_SCHEME: String,
-        CONFIG_MISSION_NUMBER: i32,
-        STATION_PARAMETERS: Vec<String>,
-        realtime_data: Option<HashMap<String, Vec<f64>>>,
-        adjusted_data: Option<HashMap<String, Vec<f64>>>,
-        data_info: Option<HashMap<String, DataInfo>>,
-        level_qc: Option<HashMap<String, Vec<String>>>,
-        adjusted_level_qc: Option<HashMap<String, Vec<String>>>,
-    }
-
-    #[derive(Serialize, Deserialize, Debug, Clone)]
-    struct MetaSchema {
-        _id: String,
-        DATA_TYPE: String,
-        FORMAT_VERSION: String,
-        HANDBOOK_VERSION: String,
-        REFERENCE_DATE_TIME: String,
-        PROJECT_NAME: String,   
-        PI_NAME: Vec<String>,
-        DATA_CENTRE: String,
-        PLATFORM_TYPE: String,
-        FLOAT_SERIAL_NO: String,
-        FIRMWARE_VERSION: String,
-        WMO_INST_TYPE: String,
-        POSITIONING_SYSTEM: String,
+fn bson_date(date: NaiveDateTime) -> bson::DateTime {
+    bson::DateTime::from_chrono(Utc.from_utc_datetime(&date))
+}
+
+// JULD-style values are days since REFERENCE_DATE_TIME, kept to the millisecond
+fn juld_to_bson_date(juld: f64, reference: NaiveDateTime) -> bson::DateTime {
+    bson_date(reference + Duration::milliseconds((juld * 86_400_000.0).round() as i64))
+}
+
+// a variable's numeric _FillValue, if it declares one
+fn fill_value(variable: &netcdf::Variable) -> Option<f64> {
+    match variable.attribute_value("_FillValue")?.ok()? {
+        netcdf::AttributeValue::Double(fill) => Some(fill),
+        netcdf::AttributeValue::Float(fill) => Some(fill as f64),
+        netcdf::AttributeValue::Int(fill) => Some(fill as f64),
+        netcdf::AttributeValue::Short(fill) => Some(fill as f64),
+        netcdf::AttributeValue::Schar(fill) => Some(fill as f64),
+        netcdf::AttributeValue::Uchar(fill) => Some(fill as f64),
+        _ => None,
     }
+}
 
-    // data unpacking /////////////////////////////////////////////
+// numeric values with fills and NaNs mapped to None
+fn unpack_optional_values(variable: &netcdf::Variable, extents: netcdf::Extents) -> Result<Vec<Option<f64>>, Box<dyn Error>> {
+    let fill = fill_value(variable);
+    let values: Vec<f64> = variable.get_values(extents)?;
+    Ok(values.into_iter().map(|value| if value.is_nan() || Some(value) == fill { None } else { Some(value) }).collect())
+}
 
-    // let mut file_names: Vec<String> = Vec::new();
-    // if let Ok(entries) = fs::read_dir(data_directory.clone()) {
-    //     for entry in entries {
-    //         if let Ok(entry) = entry {
-    //             let sub_file_path = format!("{}/{}/profiles", data_directory, entry.file_name().to_str());
-    //             if let Ok(subentries) = fs::read_dir(sub_file_path.clone()) {
-    //                 for subentry in subentries {
-    //                     if let Ok(subentry) = subentry {
-    //                         if let Some(file_name) = subentry.file_name().to_str() {
-    //                             let file_path = format!("{}/{}/profiles/{}", data_directory, entry, file_name);
-    //                             file_names.push(file_path);
-    //                         }
-    //                     }
-    //                 }
-    //             }
-    //         }
-    //     }
-    // }
-    // for file_name in &file_names {
-    //     println!("{}", file_name);
-    // }
+// a single numeric value, None if the variable is absent, unreadable or filled
+fn unpack_optional_value(name: &str, extents: netcdf::Extents, file: &netcdf::File) -> Option<f64> {
+    let variable = file.variable(name)?;
+    unpack_optional_values(&variable, extents).ok()?.into_iter().next().flatten()
+}
 
-    let mut file_names: Vec<String> = Vec::new();
-    if let Ok(entries) = fs::read_dir(data_directory.clone()) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                if let Some(file_name) = entry.file_name().to_str() {
-                    let profile_path = format!("{}/{}/profiles", data_directory, file_name);
-                    if let Ok(profile_entries) = fs::read_dir(profile_path.clone()) {
-                        for profile_entry in profile_entries {
-                            if let Ok(profile_entry) = profile_entry {
-                                if let Some(profile_file_name) = profile_entry.file_name().to_str() {
-                                    let file_path = format!("{}/{}/profiles/{}", data_directory, file_name, profile_file_name);
-                                    file_names.push(file_path);
-                                }
-                            }
-                        }
-                    }   
-                }
+// some older files fill positions with these instead of their _FillValue
+fn latitude(value: Option<f64>) -> Option<f64> {
+    value.filter(|lat| ![-99.999, -999.0].contains(lat))
+}
+
+// also wrapped into -180..180
+fn longitude(value: Option<f64>) -> Option<f64> {
+    value.filter(|lon| ![-999.999, -999.0].contains(lon))
+        .map(|lon| if lon > 180.0 {
+            lon - 360.0
+        } else if lon < -180.0 {
+            lon + 360.0
+        } else {
+            lon
+        })
+}
+
+fn is_bgc_file(file_name: &str) -> bool {
+    file_name.rsplit('/').next().unwrap_or("").starts_with('B')
+}
+
+fn is_delayed_mode(file_name: &str) -> bool {
+    file_name.rsplit('/').next().unwrap_or("").trim_start_matches(|c| c == 'B' || c == 'S').starts_with('D')
+}
+
+fn pairing_key(file_name: &str) -> String {
+    // B<R|D><wmo>_<cyc>.nc and <R|D><wmo>_<cyc>.nc both reduce to <wmo>_<cyc>, compressed or not
+    let base = file_name.rsplit('/').next().unwrap_or("").trim_end_matches(".gz");
+    base.trim_start_matches('B').trim_start_matches(|c| c == 'R' || c == 'D').to_string()
+}
+
+// pairs each B-file with the core file for the same float and cycle, so their parameters land in one document;
+// a stale R file lingering next to its D replacement is passed over, and B-files with no core file stand alone
+fn pair_files(file_names: Vec<String>) -> Vec<(String, Option<String>)> {
+    let mut bgc_files: HashMap<String, String> = HashMap::new();
+    let mut core_files: HashMap<String, String> = HashMap::new();
+    for file_name in file_names {
+        let files = if is_bgc_file(&file_name) { &mut bgc_files } else { &mut core_files };
+        let key = pairing_key(&file_name);
+        if files.get(&key).map_or(true, |existing| !is_delayed_mode(existing)) {
+            files.insert(key, file_name);
+        }
+    }
+    let mut core_files: Vec<String> = core_files.into_values().collect();
+    core_files.sort();
+    let mut file_pairs: Vec<(String, Option<String>)> = core_files.into_iter()
+        .map(|file_name| {
+            let bgc_file_name = bgc_files.remove(&pairing_key(&file_name));
+            (file_name, bgc_file_name)
+        })
+        .collect();
+    file_pairs.extend(bgc_files.into_values().map(|file_name| (file_name, None)));
+    file_pairs
+}
+
+fn pressures_match(core: &DataSchema, bgc: &DataSchema) -> bool {
+    let core_pres = core.realtime_data.as_ref().and_then(|data| data.get("PRES"));
+    let bgc_pres = bgc.realtime_data.as_ref().and_then(|data| data.get("PRES"));
+    core_pres == bgc_pres
+}
+
+fn merge_map<T>(into: &mut Option<HashMap<String, T>>, from: Option<HashMap<String, T>>) {
+    if let Some(from) = from {
+        let into = into.get_or_insert_with(HashMap::new);
+        for (param, values) in from {
+            // PRES is shared with the core file, which stays authoritative for it
+            if param != "PRES" && !param.is_empty() {
+                into.insert(param, values);
             }
         }
     }
+}
 
-    let mut meta_docs: Vec<MetaSchema> = Vec::new();
+fn merge_bgc_profile(core: &mut DataSchema, bgc: DataSchema) {
+    for param in bgc.STATION_PARAMETERS {
+        if param != "PRES" && !param.is_empty() && !core.STATION_PARAMETERS.contains(&param) {
+            core.STATION_PARAMETERS.push(param);
+        }
+    }
+    merge_map(&mut core.realtime_data, bgc.realtime_data);
+    merge_map(&mut core.adjusted_data, bgc.adjusted_data);
+    merge_map(&mut core.adjusted_error, bgc.adjusted_error);
+    merge_map(&mut core.data_info, bgc.data_info);
+    merge_map(&mut core.level_qc, bgc.level_qc);
+    merge_map(&mut core.adjusted_level_qc, bgc.adjusted_level_qc);
+    merge_map(&mut core.calibration, bgc.calibration);
+    if let Some(history) = bgc.history {
+        core.history.get_or_insert_with(Vec::new).extend(history);
+    }
+    if let (Some(provenance), Some(bgc_provenance)) = (&mut core.provenance, bgc.provenance) {
+        provenance.sources.extend(bgc_provenance.sources);
+    }
+    // the merged document is as new as the newer of its two files
+    if bgc.DATE_UPDATE > core.DATE_UPDATE {
+        core.DATE_UPDATE = bgc.DATE_UPDATE;
+        core.update_timestamp = bgc.update_timestamp;
+    }
+}
 
-    for file_name in file_names {
-        println!("Processing file: {}", file_name);
-        let id = file_name
-            .rsplit('/')
-            .next()
-            .and_then(|name| name.strip_suffix(".nc"))
-            .unwrap_or("");
-        let file = netcdf::open(&file_name)?;
-        let pindex = 0; // just use the first profile for now
-        let STRING1: usize = 1;
-        let STRING2: usize = 2;
-        let STRING4: usize = 4;
-        let STRING8: usize = 8;
-        let STRING16: usize = 16;
-        let STRING32: usize = 32;
-        let STRING64: usize = 64;
-        let STRING256: usize = 256;
-        let DATE_TIME: usize = 14;
-        let N_PROF: usize = file.dimension("N_PROF").unwrap().len();
-        let N_PARAM: usize = file.dimension("N_PARAM").unwrap().len();
-        let N_LEVELS: usize = file.dimension("N_LEVELS").unwrap().len();
-        let N_CALIB: usize = file.dimension("N_CALIB").unwrap().len();
-        //let N_HISTORY: usize = file.dimension("N_HISTORY").unwrap().len();
-    
-        let DATA_TYPE: String = unpack_string("DATA_TYPE", STRING16, [..16].into(), &file);
-        let FORMAT_VERSION: String = unpack_string("FORMAT_VERSION", STRING4, [..4].into(), &file);
-        let HANDBOOK_VERSION: String = unpack_string("HANDBOOK_VERSION", STRING4, [..4].into(), &file);
-        let REFERENCE_DATE_TIME: String = unpack_string("REFERENCE_DATE_TIME", DATE_TIME, [..14].into(), &file);
-        let DATE_CREATION: String = unpack_string("DATE_CREATION", DATE_TIME, [..14].into(), &file);
-        let DATE_UPDATE: String = unpack_string("DATE_UPDATE", DATE_TIME, [..14].into(), &file);
-        let PLATFORM_NUMBER: String = unpack_string("PLATFORM_NUMBER", STRING8, [..1, ..8].into(), &file); // encoded as metadata _id
-        let PROJECT_NAME: String = unpack_string("PROJECT_NAME", STRING64, [..1, ..64].into(), &file);
-        let PI_NAME: String = unpack_string("PI_NAME", STRING64, [..1, ..64].into(), &file);
-        let namesize: usize = file.variable("STATION_PARAMETERS").unwrap().dimensions()[2].len();
+// <PLATFORM_NUMBER>_m<hash>, hashing the fields profile files repeat for every cycle;
+// expects meta_object._id to still be the bare platform number
+fn meta_content_id(meta_object: &MetaSchema) -> String {
+    let fields = [
+        meta_object.DATA_TYPE.as_str(),
+        meta_object.FORMAT_VERSION.as_str(),
+        meta_object.HANDBOOK_VERSION.as_str(),
+        meta_object.REFERENCE_DATE_TIME.as_str(),
+        meta_object.PROJECT_NAME.as_str(),
+        &meta_object.PI_NAME.join(","),
+        meta_object.DATA_CENTRE.as_str(),
+        meta_object.PLATFORM_TYPE.as_str(),
+        meta_object.FLOAT_SERIAL_NO.as_str(),
+        meta_object.FIRMWARE_VERSION.as_str(),
+        meta_object.WMO_INST_TYPE.as_str(),
+        meta_object.POSITIONING_SYSTEM.as_str(),
+    ];
+    let mut hasher = Sha256::new();
+    for field in fields {
+        hasher.update(field.as_bytes());
+        hasher.update([0x1f]); // unit separator, so shifting text between fields changes the hash
+    }
+    let digest = format!("{:x}", hasher.finalize());
+    format!("{}_m{}", meta_object._id, &digest[..16])
+}
+
+fn cross_check(entry: &index::IndexEntry, data_object: &DataSchema, meta_object: &MetaSchema) {
+    // the index describes the first profile in each file
+    if let Some(geolocation) = &data_object.geolocation {
+        let [longitude, latitude] = geolocation.coordinates;
+        if entry.latitude.map_or(false, |lat| (lat - latitude).abs() > 0.01) || entry.longitude.map_or(false, |lon| (lon - longitude).abs() > 0.01) {
+            eprintln!("{}: index position ({:?}, {:?}) differs from file ({}, {})", entry.file, entry.latitude, entry.longitude, latitude, longitude);
+        }
+    }
+    if entry.institution != meta_object.DATA_CENTRE {
+        eprintln!("{}: index institution {} differs from DATA_CENTRE {}", entry.file, entry.institution, meta_object.DATA_CENTRE);
+    }
+    if entry.date_update != data_object.DATE_UPDATE {
+        eprintln!("{}: index date_update {} differs from DATE_UPDATE {}", entry.file, entry.date_update, data_object.DATE_UPDATE);
+    }
+}
+
+// per-file extraction ///////////////////////////////////////////
+
+fn extract_profiles(file_name: &str, file: &netcdf::File) -> Result<Vec<(DataSchema, MetaSchema)>, Box<dyn Error>> {
+    let stem = file_stem(file_name);
+    let STRING1: usize = 1;
+    let STRING2: usize = 2;
+    let STRING4: usize = 4;
+    let STRING8: usize = 8;
+    let STRING16: usize = 16;
+    let STRING32: usize = 32;
+    let STRING64: usize = 64;
+    let STRING256: usize = 256;
+    let DATE_TIME: usize = 14;
+    let N_PROF: usize = file.dimension("N_PROF").ok_or("No N_PROF dimension")?.len();
+    let N_PARAM: usize = file.dimension("N_PARAM").ok_or("No N_PARAM dimension")?.len();
+    let N_LEVELS: usize = file.dimension("N_LEVELS").ok_or("No N_LEVELS dimension")?.len();
+    let N_CALIB: usize = file.dimension("N_CALIB").map(|d| d.len()).unwrap_or(0);
+    let N_HISTORY: usize = file.dimension("N_HISTORY").map(|d| d.len()).unwrap_or(0); // unlimited, and often empty
+
+    // file-level variables, shared by every profile in the file
+    let DATA_TYPE: String = unpack_string("DATA_TYPE", STRING16, [..16].into(), &file);
+    let FORMAT_VERSION: String = unpack_string("FORMAT_VERSION", STRING4, [..4].into(), &file);
+    let HANDBOOK_VERSION: String = unpack_string("HANDBOOK_VERSION", STRING4, [..4].into(), &file);
+    let REFERENCE_DATE_TIME: String = unpack_string("REFERENCE_DATE_TIME", DATE_TIME, [..14].into(), &file);
+    let DATE_CREATION: String = unpack_string("DATE_CREATION", DATE_TIME, [..14].into(), &file);
+    let DATE_UPDATE: String = unpack_string("DATE_UPDATE", DATE_TIME, [..14].into(), &file);
+    // JULD counts days from REFERENCE_DATE_TIME, which Argo fixes at 1950-01-01 if a file leaves it blank
+    let reference_date = parse_argo_date(&REFERENCE_DATE_TIME)
+        .unwrap_or_else(|| NaiveDate::from_ymd_opt(1950, 1, 1).and_then(|date| date.and_hms_opt(0, 0, 0)).unwrap());
+    let file_kind = detect_file_kind(file_name, &DATA_TYPE);
+    let namesize: usize = file.variable("STATION_PARAMETERS").ok_or("No STATION_PARAMETERS variable")?.dimensions()[2].len();
+    let namebuf: usize = match namesize {
+        1 => STRING1,
+        2 => STRING2,
+        4 => STRING4,
+        8 => STRING8,
+        16 => STRING16,
+        32 => STRING32,
+        64 => STRING64,
+        256 => STRING256,
+        _ => return Err(format!("Unsupported namesize: {}", namesize).into()),
+    };
+
+    let mut profiles: Vec<(DataSchema, MetaSchema)> = Vec::new();
+    for pindex in 0..N_PROF {
+        let id = profile_id(stem, pindex);
+        let p = pindex..pindex+1; // this profile's slot along N_PROF
+        let PLATFORM_NUMBER: String = unpack_string("PLATFORM_NUMBER", STRING8, [p.clone(), 0..8].into(), &file); // encoded as metadata _id
+        let PROJECT_NAME: String = unpack_string("PROJECT_NAME", STRING64, [p.clone(), 0..64].into(), &file);
+        let PI_NAME: String = unpack_string("PI_NAME", STRING64, [p.clone(), 0..64].into(), &file);
         let STATION_PARAMETERS: Vec<String> = unpack_string_array(
             "STATION_PARAMETERS",
-            match namesize {
-                1 => STRING1,
-                2 => STRING2,
-                4 => STRING4,
-                8 => STRING8,
-                16 => STRING16,
-                32 => STRING32,
-                64 => STRING64,
-                256 => STRING256,
-                _ => panic!("Unsupported namesize: {}", namesize),
-            },
+            namebuf,
             N_PARAM,
-            [..1, ..N_PARAM, ..namesize].into(),
+            [p.clone(), 0..N_PARAM, 0..namesize].into(),
             &file,
         );
-        let CYCLE_NUMBER: i32 = file.variable("CYCLE_NUMBER").map(|var| var.get_value([pindex]).unwrap_or(99999)).unwrap_or(99999);
-        let DIRECTION: String = unpack_string("DIRECTION", STRING1, [..1].into(), &file);
-        let DATA_CENTRE: String = unpack_string("DATA_CENTRE", STRING2, [..1, ..2].into(), &file);
-        let DC_REFERENCE: String = unpack_string("DC_REFERENCE", STRING32, [..1, ..32].into(), &file);
-        let DATA_STATE_INDICATOR: String = unpack_string("DATA_STATE_INDICATOR", STRING4, [..1, ..4].into(), &file);
-        let DATA_MODE: String = unpack_string("DATA_MODE", STRING1, [..1].into(), &file);
-        let PLATFORM_TYPE: String = unpack_string("PLATFORM_TYPE", STRING32, [..1, ..32].into(), &file);
-        let FLOAT_SERIAL_NO: String = unpack_string("FLOAT_SERIAL_NO", STRING32, [..1, ..32].into(), &file);
-        let FIRMWARE_VERSION: String = unpack_string("FIRMWARE_VERSION", STRING32, [..1, ..32].into(), &file);
-        let WMO_INST_TYPE: String = unpack_string("WMO_INST_TYPE", STRING4, [..1, ..4].into(), &file);
-        let JULD: f64 = file.variable("JULD").map(|var| var.get_value([pindex]).unwrap_or(999999.0)).unwrap_or(999999.0);
-        let JULD_QC: String = unpack_string("JULD_QC", STRING1, [..1].into(), &file);
-        let JULD_LOCATION: f64 = file.variable("JULD_LOCATION").map(|var| var.get_value([pindex]).unwrap_or(999999.0)).unwrap_or(999999.0);
-        let mut LATITUDE: f64 = file.variable("LATITUDE").map(|var| var.get_value([pindex]).unwrap_or(99999.0)).unwrap_or(99999.0);
-        let mut LONGITUDE: f64 = file.variable("LONGITUDE").map(|var| var.get_value([pindex]).unwrap_or(99999.0)).unwrap_or(99999.0);
-        let latitude_fills = [99999.0, -99.999, -999.0];
-        let longitude_fills = [99999.0, -999.999, -999.0]; 
-        if latitude_fills.contains(&LATITUDE) || longitude_fills.contains(&LONGITUDE) || LATITUDE.is_nan() || LONGITUDE.is_nan() {
-            LATITUDE = -90.0;
-            LONGITUDE = 0.0;
-        }
-        LONGITUDE = if LONGITUDE > 180.0 {
-            LONGITUDE - 360.0
-        } else if LONGITUDE < -180.0 {
-            LONGITUDE + 360.0
-        } else {
-            LONGITUDE
-        };
-        let POSITION_QC: String = unpack_string("POSITION_QC", STRING1, [..1].into(), &file);
-        let POSITIONING_SYSTEM: String = unpack_string("POSITIONING_SYSTEM", STRING8, [..1, ..8].into(), &file);
-        let VERTICAL_SAMPLING_SCHEME: String = unpack_string("VERTICAL_SAMPLING_SCHEME", STRING256, [..1, ..256].into(), &file);
-        let CONFIG_MISSION_NUMBER: i32 = file.variable("CONFIG_MISSION_NUMBER").map(|var| var.get_value([pindex]).unwrap_or(99999)).unwrap_or(99999);
+        let CYCLE_NUMBER: Option<i32> = unpack_optional_value("CYCLE_NUMBER", [p.clone()].into(), &file).map(|cycle| cycle as i32);
+        let DIRECTION: String = unpack_string("DIRECTION", STRING1, [p.clone()].into(), &file);
+        let DATA_CENTRE: String = unpack_string("DATA_CENTRE", STRING2, [p.clone(), 0..2].into(), &file);
+        let DC_REFERENCE: String = unpack_string("DC_REFERENCE", STRING32, [p.clone(), 0..32].into(), &file);
+        let DATA_STATE_INDICATOR: String = unpack_string("DATA_STATE_INDICATOR", STRING4, [p.clone(), 0..4].into(), &file);
+        let DATA_MODE: String = unpack_string("DATA_MODE", STRING1, [p.clone()].into(), &file);
+        let PLATFORM_TYPE: String = unpack_string("PLATFORM_TYPE", STRING32, [p.clone(), 0..32].into(), &file);
+        let FLOAT_SERIAL_NO: String = unpack_string("FLOAT_SERIAL_NO", STRING32, [p.clone(), 0..32].into(), &file);
+        let FIRMWARE_VERSION: String = unpack_string("FIRMWARE_VERSION", STRING32, [p.clone(), 0..32].into(), &file);
+        let WMO_INST_TYPE: String = unpack_string("WMO_INST_TYPE", STRING4, [p.clone(), 0..4].into(), &file);
+        let JULD: Option<f64> = unpack_optional_value("JULD", [p.clone()].into(), &file);
+        let JULD_QC: String = unpack_string("JULD_QC", STRING1, [p.clone()].into(), &file);
+        let JULD_LOCATION: Option<f64> = unpack_optional_value("JULD_LOCATION", [p.clone()].into(), &file);
+        // some files still carry sentinels other than their declared _FillValue
+        let LATITUDE: Option<f64> = latitude(unpack_optional_value("LATITUDE", [p.clone()].into(), &file));
+        let LONGITUDE: Option<f64> = longitude(unpack_optional_value("LONGITUDE", [p.clone()].into(), &file));
+        let POSITION_QC: String = unpack_string("POSITION_QC", STRING1, [p.clone()].into(), &file);
+        let POSITIONING_SYSTEM: String = unpack_string("POSITIONING_SYSTEM", STRING8, [p.clone(), 0..8].into(), &file);
+        let VERTICAL_SAMPLING_SCHEME: String = unpack_string("VERTICAL_SAMPLING_SCHEME", STRING256, [p.clone(), 0..256].into(), &file);
+        let CONFIG_MISSION_NUMBER: Option<i32> = unpack_optional_value("CONFIG_MISSION_NUMBER", [p.clone()].into(), &file).map(|mission| mission as i32);
 
         let PARAMETER_DATA_MODE: Vec<String> = if let Some(variable) = file.variable("PARAMETER_DATA_MODE") {
-            unpack_string_array("PARAMETER_DATA_MODE", STRING1, N_PARAM, [..1, ..N_PARAM].into(), &file)
+            unpack_string_array("PARAMETER_DATA_MODE", STRING1, N_PARAM, [p.clone(), 0..N_PARAM].into(), &file)
         } else {
             vec![DATA_MODE.clone(); STATION_PARAMETERS.len()]
         };
-        
+        // synthetic files only carry per-parameter data modes
+        let DATA_MODE: String = if DATA_MODE.is_empty() && file_kind == FileKind::Synthetic {
+            profile_data_mode(&PARAMETER_DATA_MODE)
+        } else {
+            DATA_MODE
+        };
+    
         // fiddling with templated unpacking, tbd how to consume this downstream
         // could also turn all these into functions
 
-        let realtime_data: Option<HashMap<String, Vec<f64>>> = STATION_PARAMETERS.iter()
+        let realtime_data: Option<HashMap<String, Vec<Option<f64>>>> = STATION_PARAMETERS.iter()
             .map(|param| {
                 if param.is_empty() {
                     Ok((param.clone(), vec![]))
                 } else {
                     match file.variable(param) {
                         Some(variable) => {
-                            let data: Vec<f64> = variable.get_values([..1, ..N_LEVELS])?;
+                            let data: Vec<Option<f64>> = unpack_optional_values(&variable, [p.clone(), 0..N_LEVELS].into())?;
                             Ok((param.clone(), data))
                         },
                         None => Ok((param.clone(), vec![])),
@@ -288,8 +434,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
             .collect::<Result<_, Box<dyn Error>>>()
             .map(Some)
             .unwrap_or(None);
-    
-        let adjusted_data: Option<HashMap<String, Vec<f64>>> = STATION_PARAMETERS.iter()
+
+        let adjusted_data: Option<HashMap<String, Vec<Option<f64>>>> = STATION_PARAMETERS.iter()
             .enumerate()
             .map(|(i, param)| {
                 if param.is_empty() {
@@ -302,7 +448,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         let adjusted_variable_name = format!("{}_ADJUSTED", param);
                         match file.variable(&adjusted_variable_name) {
                             Some(variable) => {
-                                let data: Vec<f64> = variable.get_values([..1, ..N_LEVELS])?;
+                                let data: Vec<Option<f64>> = unpack_optional_values(&variable, [p.clone(), 0..N_LEVELS].into())?;
                                 Ok((param.clone(), data))
                             },
                             None => Ok((param.clone(), vec![])),
@@ -319,16 +465,17 @@ async fn main() -> Result<(), Box<dyn Error>> {
             .map(|(i, param)| {
                 if param.is_empty() {
                     Ok((param.clone(), DataInfo {
-                        DATA_MODE: "".to_string(),
+                        DATA_MODE: DataMode::Blank,
                         UNITS: "".to_string(),
                         LONG_NAME: "".to_string(),
                         PROFILE_PARAMETER_QC: "".to_string(),
                     }))
                 } else {
                     let data_mode = PARAMETER_DATA_MODE.get(i).cloned().unwrap_or(DATA_MODE.clone());
-                    if data_mode == "R" || param == "NB_SAMPLE_CTD" {
+                    // BGC and synthetic files mix realtime and adjusted parameters, so describe all of them
+                    if (data_mode == "R" && file_kind == FileKind::Core) || param == "NB_SAMPLE_CTD" {
                         Ok((param.clone(), DataInfo {
-                            DATA_MODE: "".to_string(),
+                            DATA_MODE: DataMode::Blank,
                             UNITS: "".to_string(),
                             LONG_NAME: "".to_string(),
                             PROFILE_PARAMETER_QC: "".to_string(),
@@ -337,14 +484,14 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         match file.variable(param) {
                             Some(variable) => {
                                 let data_mode = PARAMETER_DATA_MODE.get(i).cloned().unwrap_or(DATA_MODE.clone());
-                                let units = variable.attribute_value("units").unwrap()?;
-                                let long_name = variable.attribute_value("long_name").unwrap()?;
+                                let units = variable.attribute_value("units").ok_or(format!("{} has no units", param))??;
+                                let long_name = variable.attribute_value("long_name").ok_or(format!("{} has no long_name", param))??;
                                 let qc_variable_name = format!("PROFILE_{}_QC", param);
-                                let qc_value = unpack_string(&qc_variable_name, STRING1, [..1].into(), &file);
+                                let qc_value = unpack_string(&qc_variable_name, STRING1, [p.clone()].into(), &file);
                                 if let netcdf::AttributeValue::Str(u) = units {
                                     if let netcdf::AttributeValue::Str(l) = long_name {
                                         Ok((param.clone(), DataInfo {
-                                            DATA_MODE: data_mode,
+                                            DATA_MODE: DataMode::parse(&data_mode),
                                             UNITS: u.to_string(),
                                             LONG_NAME: l.to_string(),
                                             PROFILE_PARAMETER_QC: qc_value,
@@ -357,7 +504,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                 } 
                             },
                             None => Ok((param.clone(), DataInfo {
-                                DATA_MODE: "".to_string(),
+                                DATA_MODE: DataMode::Blank,
                                 UNITS: "".to_string(),
                                 LONG_NAME: "".to_string(),
                                 PROFILE_PARAMETER_QC: "".to_string(),
@@ -369,22 +516,22 @@ async fn main() -> Result<(), Box<dyn Error>> {
             .collect::<Result<_, Box<dyn Error>>>()
             .map(Some)
             .unwrap_or(None);
-    
-        let level_qc: Option<HashMap<String, Vec<String>>> = STATION_PARAMETERS.iter()
+
+        let level_qc: Option<HashMap<String, Vec<QcFlag>>> = STATION_PARAMETERS.iter()
             .map(|param| {
                 if param.is_empty() {
                     Ok((param.clone(), vec![]))
                 } else {
                     let qc_variable_name = format!("{}_QC", param);
-                    let qc_vec = unpack_string_array(&qc_variable_name, STRING1, N_LEVELS, [..1, ..N_LEVELS].into(), &file);
-                    Ok((param.clone(), qc_vec))
+                    let qc_vec = unpack_string_array(&qc_variable_name, STRING1, N_LEVELS, [p.clone(), 0..N_LEVELS].into(), &file);
+                    Ok((param.clone(), qc_vec.iter().map(|flag| QcFlag::parse(flag)).collect()))
                 }
             })
             .collect::<Result<_, Box<dyn Error>>>()
             .map(Some)
             .unwrap_or(None);
-            
-        let adjusted_level_qc: Option<HashMap<String, Vec<String>>> = STATION_PARAMETERS.iter()
+        
+        let adjusted_level_qc: Option<HashMap<String, Vec<QcFlag>>> = STATION_PARAMETERS.iter()
             .enumerate()
             .map(|(i, param)| {
                 if param.is_empty() {
@@ -395,32 +542,107 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         Ok((param.clone(), vec![]))
                     } else {
                         let qc_variable_name = format!("{}_ADJUSTED_QC", param);
-                        let qc_vec = unpack_string_array(&qc_variable_name, STRING1, N_LEVELS, [..1, ..N_LEVELS].into(), &file);
-                        Ok((param.clone(), qc_vec))
+                        let qc_vec = unpack_string_array(&qc_variable_name, STRING1, N_LEVELS, [p.clone(), 0..N_LEVELS].into(), &file);
+                        Ok((param.clone(), qc_vec.iter().map(|flag| QcFlag::parse(flag)).collect()))
                     }
                 }
             })
             .collect::<Result<_, Box<dyn Error>>>()
             .map(Some)
             .unwrap_or(None);
-            
-        // let adjusted_level_error: HashMap<String, Vec<f64>> = STATION_PARAMETERS.iter()
-        //     .map(|param| {
-        //         let adjusted_variable_name = format!("{}_ADJUSTED_ERROR", param);
-        //         let variable = file.variable(&adjusted_variable_name).expect(&format!("Could not find variable '{}'", adjusted_variable_name));
-        //         let data: Vec<f64> = variable.get_values([..1, ..N_LEVELS])?;
-        //         Ok((param.clone(), data))
-        //     })
-        //     .collect::<Result<_, Box<dyn Error>>>()?;
         
-        // construct the structs for this file ///////////////////////////////
-    
-        let mut meta_object = MetaSchema {
+        let adjusted_error: Option<HashMap<String, Vec<Option<f64>>>> = STATION_PARAMETERS.iter()
+            .enumerate()
+            .map(|(i, param)| {
+                if param.is_empty() {
+                    Ok((param.clone(), vec![]))
+                } else {
+                    let data_mode = PARAMETER_DATA_MODE.get(i).cloned().unwrap_or(DATA_MODE.clone());
+                    if data_mode == "R" || param == "NB_SAMPLE_CTD" {
+                        Ok((param.clone(), vec![]))
+                    } else {
+                        // not every parameter carries an error estimate, so a missing variable is just an empty array
+                        let error_variable_name = format!("{}_ADJUSTED_ERROR", param);
+                        match file.variable(&error_variable_name) {
+                            Some(variable) => {
+                                let data: Vec<Option<f64>> = unpack_optional_values(&variable, [p.clone(), 0..N_LEVELS].into())?;
+                                Ok((param.clone(), data))
+                            },
+                            None => Ok((param.clone(), vec![])),
+                        }
+                    }
+                }
+            })
+            .collect::<Result<_, Box<dyn Error>>>()
+            .map(Some)
+            .unwrap_or(None);
+        
+        // synthetic profiles record how far each BGC observation was moved onto the PRES axis
+        let dpres: Option<HashMap<String, Vec<Option<f64>>>> = if file_kind == FileKind::Synthetic {
+            STATION_PARAMETERS.iter()
+                .filter(|param| !param.is_empty())
+                .filter_map(|param| file.variable(&format!("{}_dPRES", param)).map(|variable| (param, variable)))
+                .map(|(param, variable)| {
+                    let data: Vec<Option<f64>> = unpack_optional_values(&variable, [p.clone(), 0..N_LEVELS].into())?;
+                    Ok((param.clone(), data))
+                })
+                .collect::<Result<_, Box<dyn Error>>>()
+                .map(Some)
+                .unwrap_or(None)
+        } else {
+            None
+        };
+
+        let mut history: Vec<HistoryRecord> = Vec::new();
+        for hindex in 0..N_HISTORY {
+            let record = HistoryRecord {
+                HISTORY_INSTITUTION: unpack_history_string("HISTORY_INSTITUTION", hindex, pindex, &file),
+                HISTORY_STEP: unpack_history_string("HISTORY_STEP", hindex, pindex, &file),
+                HISTORY_SOFTWARE: unpack_history_string("HISTORY_SOFTWARE", hindex, pindex, &file),
+                HISTORY_SOFTWARE_RELEASE: unpack_history_string("HISTORY_SOFTWARE_RELEASE", hindex, pindex, &file),
+                HISTORY_REFERENCE: unpack_history_string("HISTORY_REFERENCE", hindex, pindex, &file),
+                HISTORY_DATE: unpack_history_string("HISTORY_DATE", hindex, pindex, &file),
+                HISTORY_ACTION: unpack_history_string("HISTORY_ACTION", hindex, pindex, &file),
+                HISTORY_PARAMETER: unpack_history_string("HISTORY_PARAMETER", hindex, pindex, &file),
+                HISTORY_START_PRES: unpack_optional_value("HISTORY_START_PRES", [hindex..hindex+1, p.clone()].into(), &file),
+                HISTORY_STOP_PRES: unpack_optional_value("HISTORY_STOP_PRES", [hindex..hindex+1, p.clone()].into(), &file),
+                HISTORY_PREVIOUS_VALUE: unpack_optional_value("HISTORY_PREVIOUS_VALUE", [hindex..hindex+1, p.clone()].into(), &file),
+                HISTORY_QCTEST: unpack_history_string("HISTORY_QCTEST", hindex, pindex, &file),
+            };
+            // N_HISTORY is sized for the profile with the longest history, so other profiles have blank rows
+            if record.HISTORY_INSTITUTION.is_empty() && record.HISTORY_STEP.is_empty() && record.HISTORY_ACTION.is_empty() {
+                continue;
+            }
+            history.push(record);
+        }
+
+        // calibrations are keyed by the PARAMETER they apply to, one record per N_CALIB
+        let mut calibration: HashMap<String, Vec<CalibrationRecord>> = HashMap::new();
+        for cindex in 0..N_CALIB {
+            for param_index in 0..N_PARAM {
+                let parameter = unpack_calib_string("PARAMETER", pindex, cindex, param_index, &file);
+                if parameter.is_empty() {
+                    continue;
+                }
+                let record = CalibrationRecord {
+                    SCIENTIFIC_CALIB_EQUATION: unpack_calib_string("SCIENTIFIC_CALIB_EQUATION", pindex, cindex, param_index, &file),
+                    SCIENTIFIC_CALIB_COEFFICIENT: unpack_calib_string("SCIENTIFIC_CALIB_COEFFICIENT", pindex, cindex, param_index, &file),
+                    SCIENTIFIC_CALIB_COMMENT: unpack_calib_string("SCIENTIFIC_CALIB_COMMENT", pindex, cindex, param_index, &file),
+                    SCIENTIFIC_CALIB_DATE: unpack_calib_string("SCIENTIFIC_CALIB_DATE", pindex, cindex, param_index, &file),
+                };
+                calibration.entry(parameter).or_insert_with(Vec::new).push(record);
+            }
+        }
+
+        // construct the structs for this profile ///////////////////////////////
+
+        let meta_object = MetaSchema {
             _id: PLATFORM_NUMBER.clone(),
-            DATA_TYPE: DATA_TYPE,
-            FORMAT_VERSION: FORMAT_VERSION,
-            HANDBOOK_VERSION: HANDBOOK_VERSION,
-            REFERENCE_DATE_TIME: REFERENCE_DATE_TIME,
+            schema_version: SCHEMA_VERSION,
+            DATA_TYPE: DATA_TYPE.clone(),
+            FORMAT_VERSION: FORMAT_VERSION.clone(),
+            HANDBOOK_VERSION: HANDBOOK_VERSION.clone(),
+            REFERENCE_DATE_TIME: REFERENCE_DATE_TIME.clone(),
             PROJECT_NAME: PROJECT_NAME,
             PI_NAME: split_string(PI_NAME, ','),
             DATA_CENTRE: DATA_CENTRE,
@@ -429,70 +651,864 @@ async fn main() -> Result<(), Box<dyn Error>> {
             FIRMWARE_VERSION: FIRMWARE_VERSION,
             WMO_INST_TYPE: WMO_INST_TYPE,
             POSITIONING_SYSTEM: POSITIONING_SYSTEM,
+            PLATFORM_NUMBER: Some(PLATFORM_NUMBER.clone()),
+            ..Default::default()
         };
 
-        // check if this metadata object already exists in the database
-        let mut meta_id = String::new();
-        for meta_doc in meta_docs.iter() {
-            if meta_doc.DATA_TYPE == meta_object.DATA_TYPE
-                && meta_doc.FORMAT_VERSION == meta_object.FORMAT_VERSION
-                && meta_doc.HANDBOOK_VERSION == meta_object.HANDBOOK_VERSION
-                && meta_doc.REFERENCE_DATE_TIME == meta_object.REFERENCE_DATE_TIME
-                && meta_doc.PROJECT_NAME == meta_object.PROJECT_NAME
-                && meta_doc.PI_NAME == meta_object.PI_NAME
-                && meta_doc.DATA_CENTRE == meta_object.DATA_CENTRE
-                && meta_doc.PLATFORM_TYPE == meta_object.PLATFORM_TYPE
-                && meta_doc.FLOAT_SERIAL_NO == meta_object.FLOAT_SERIAL_NO
-                && meta_doc.FIRMWARE_VERSION == meta_object.FIRMWARE_VERSION
-                && meta_doc.WMO_INST_TYPE == meta_object.WMO_INST_TYPE
-                && meta_doc.POSITIONING_SYSTEM == meta_object.POSITIONING_SYSTEM
-            {
-                meta_id = meta_doc._id.clone();
-                break;
-            }
-        }
-
-        if meta_id.is_empty() {
-            // we found a new metadata doc
-            let new_id = format!("{}_m{}", PLATFORM_NUMBER, meta_docs.len());
-            meta_object._id = new_id.clone();
-            meta_docs.push(meta_object.clone());
-            argo_meta.insert_one(meta_object, None).await?;
-            meta_id = new_id;
-        }
-
         let data_object = DataSchema {
             _id: id.to_string(),
-            geolocation: GeoJSONPoint {
+            schema_version: SCHEMA_VERSION,
+            geolocation: LONGITUDE.zip(LATITUDE).map(|(lon, lat)| GeoJSONPoint {
                 location_type: "Point".to_string(),
-                coordinates: [LONGITUDE, LATITUDE],
-            },
-            metadata: vec![meta_id.clone()],
+                coordinates: [lon, lat],
+            }),
+            position_missing: LONGITUDE.is_none() || LATITUDE.is_none(),
+            time_missing: JULD.is_none(),
+            metadata: vec![], // filled in once the metadata has been deduplicated
             CYCLE_NUMBER: CYCLE_NUMBER,
-            DIRECTION: DIRECTION,
+            DIRECTION: Direction::parse(&DIRECTION),
             DATA_STATE_INDICATOR: DATA_STATE_INDICATOR,
-            DATA_MODE: DATA_MODE,
-            DATE_CREATION: DATE_CREATION,
-            DATE_UPDATE: DATE_UPDATE,
+            DATA_MODE: DataMode::parse(&DATA_MODE),
+            DATE_CREATION: DATE_CREATION.clone(),
+            DATE_UPDATE: DATE_UPDATE.clone(),
             DC_REFERENCE: DC_REFERENCE,
             JULD: JULD,
-            JULD_QC: JULD_QC,
+            JULD_QC: QcFlag::parse(&JULD_QC),
             JULD_LOCATION: JULD_LOCATION,
-            POSITION_QC: POSITION_QC,
+            timestamp: JULD.map(|juld| juld_to_bson_date(juld, reference_date)),
+            location_timestamp: JULD_LOCATION.map(|juld| juld_to_bson_date(juld, reference_date)),
+            creation_timestamp: parse_argo_date(&DATE_CREATION).map(bson_date),
+            update_timestamp: parse_argo_date(&DATE_UPDATE).map(bson_date),
+            POSITION_QC: QcFlag::parse(&POSITION_QC),
             VERTICAL_SAMPLING_SCHEME: VERTICAL_SAMPLING_SCHEME,
             CONFIG_MISSION_NUMBER: CONFIG_MISSION_NUMBER,
             STATION_PARAMETERS: STATION_PARAMETERS,
             realtime_data: realtime_data,
             adjusted_data: adjusted_data,
+            adjusted_error: adjusted_error,
             data_info: data_info,
             level_qc: level_qc,
             adjusted_level_qc: adjusted_level_qc,
+            dpres: dpres,
+            history: Some(history),
+            calibration: Some(calibration),
+            provenance: None, // filled in by decode_file, which knows where the file sits
         };
+
+        profiles.push((data_object, meta_object));
+    }
+
+    Ok(profiles)
+}
+
+fn source_file(file_name: &str, data_directory: &str, contents: &[u8]) -> SourceFile {
+    let path = file_name.strip_prefix(data_directory).unwrap_or(file_name).trim_start_matches('/');
+    // mirrors and snapshots keep .../dac/<dac>/...; otherwise the data directory is the DAC's own directory
+    let dac = match path.split('/').skip_while(|part| *part != "dac").nth(1) {
+        Some(dac) => dac,
+        None => data_directory.trim_end_matches('/').rsplit('/').next().unwrap_or(""),
+    };
+    SourceFile {
+        path: path.to_string(),
+        dac: dac.to_string(),
+        sha256: format!("{:x}", Sha256::digest(contents)),
+    }
+}
+
+// a profile file to decode: a path on disk, or an archive member that's already been read
+struct ProfileInput {
+    name: String,
+    contents: Option<Vec<u8>>,
+}
+
+impl ProfileInput {
+    fn path(name: String) -> ProfileInput {
+        ProfileInput { name, contents: None }
+    }
+}
+
+// opens and decodes one file, with failures labelled by stage
+fn decode_file(input: &ProfileInput, data_directory: &str) -> Result<Vec<(DataSchema, MetaSchema)>, FileFailure> {
+    let file_name = input.name.as_str();
+    let contents = match &input.contents {
+        Some(contents) => Cow::Borrowed(contents.as_slice()),
+        None => Cow::Owned(fs::read(file_name).map_err(|e| FileFailure::new(file_name, "open", e))?),
+    };
+    let source = source_file(file_name, data_directory, &contents);
+    let profiles = if input.contents.is_none() && !archive::is_gzip(file_name) {
+        let file = netcdf::open(file_name).map_err(|e| FileFailure::new(file_name, "open", e))?;
+        extract_profiles(file_name, &file)
+    } else {
+        // compressed files and archive members are opened from memory rather than unpacked to disk
+        let contents = archive::decompress(file_name, &contents).map_err(|e| FileFailure::new(file_name, "open", e))?;
+        let file = netcdf::open_mem(Some(file_name), &contents).map_err(|e| FileFailure::new(file_name, "open", e))?;
+        extract_profiles(file_name, &file)
+    };
+    let mut profiles = profiles.map_err(|e| FileFailure::new(file_name, "extract", e))?;
+    let ingested = bson::DateTime::now();
+    for (data_object, _) in profiles.iter_mut() {
+        data_object.provenance = Some(Provenance {
+            sources: vec![source.clone()],
+            converter_version: env!("CARGO_PKG_VERSION").to_string(),
+            ingested,
+        });
+    }
+    Ok(profiles)
+}
+
+// decodes one core file, merged with its B-file if there is one; runs on a blocking thread
+fn decode_job(file: &ProfileInput, bgc_file: Option<&ProfileInput>, data_directory: &str, index_entries: &HashMap<String, index::IndexEntry>, saar_atlas: Option<&gsw::SaarAtlas>) -> Result<Vec<(DataSchema, MetaSchema)>, FileFailure> {
+    let file_name = file.name.as_str();
+    eprintln!("Processing file: {}", file_name);
+    let mut profiles = decode_file(file, data_directory)?;
+    if let (Some(entry), Some((data_object, meta_object))) = (index_entries.get(file_name), profiles.first()) {
+        cross_check(entry, data_object, meta_object);
+    }
+
+    if let Some(bgc_file) = bgc_file {
+        let bgc_file_name = bgc_file.name.as_str();
+        eprintln!("Merging BGC file: {}", bgc_file_name);
+        let bgc_profiles = decode_file(bgc_file, data_directory)?;
+        if let (Some(entry), Some((data_object, meta_object))) = (index_entries.get(bgc_file_name), bgc_profiles.first()) {
+            cross_check(entry, data_object, meta_object);
+        }
+        let mut unmerged = Vec::new();
+        for (pindex, bgc_profile) in bgc_profiles.into_iter().enumerate() {
+            match profiles.get_mut(pindex) {
+                Some((data_object, _)) if pressures_match(data_object, &bgc_profile.0) => {
+                    merge_bgc_profile(data_object, bgc_profile.0);
+                },
+                _ => {
+                    eprintln!("PRES mismatch between {} and {}, storing profile {} separately", file_name, bgc_file_name, pindex);
+                    unmerged.push(bgc_profile);
+                }
+            }
+        }
+        profiles.extend(unmerged);
+    }
+
+    // after merging, so each document gets the variables once
+    if let Some(saar_atlas) = saar_atlas {
+        for (data_object, _) in profiles.iter_mut() {
+            derived::add_derived_variables(data_object, saar_atlas);
+        }
+    }
+
+    Ok(profiles)
+}
+
+// where the next core file and its B-file come from: paths to open, or members streamed out of an archive
+enum PendingFiles {
+    Paths(std::vec::IntoIter<(String, Option<String>)>),
+    Archive(tokio::sync::mpsc::Receiver<(ProfileInput, Option<ProfileInput>)>),
+}
+
+impl PendingFiles {
+    async fn next(&mut self) -> Option<(ProfileInput, Option<ProfileInput>)> {
+        match self {
+            PendingFiles::Paths(pairs) => pairs.next().map(|(file_name, bgc_file_name)| (ProfileInput::path(file_name), bgc_file_name.map(ProfileInput::path))),
+            PendingFiles::Archive(receiver) => receiver.recv().await,
+        }
+    }
+}
+
+// failure reporting ///////////////////////////////////////////
+
+// a file that couldn't be converted: open, extract, lookup, metadata or write
+#[derive(Serialize, Debug)]
+struct FileFailure {
+    file: String,
+    stage: String,
+    error: String,
+}
+
+impl FileFailure {
+    fn new(file: &str, stage: &str, error: impl std::fmt::Display) -> FileFailure {
+        FileFailure {
+            file: file.to_string(),
+            stage: stage.to_string(),
+            error: error.to_string(),
+        }
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+// writes the failures as CSV if the report path ends in .csv, JSON otherwise,
+// and fails the run if there are more of them than max_failures allows
+fn report_failures(failures: &[FileFailure], report_file: Option<&str>, max_failures: usize) -> Result<(), Box<dyn Error>> {
+    for failure in failures {
+        eprintln!("Failed to {} {}: {}", failure.stage, failure.file, failure.error);
+    }
+    if let Some(report_file) = report_file {
+        if report_file.ends_with(".csv") {
+            let mut report = String::from("file,stage,error\n");
+            for failure in failures {
+                report.push_str(&format!("{},{},{}\n", csv_field(&failure.file), csv_field(&failure.stage), csv_field(&failure.error)));
+            }
+            fs::write(report_file, report)?;
+        } else {
+            fs::write(report_file, serde_json::to_vec_pretty(failures)?)?;
+        }
+    }
+    if failures.len() > max_failures {
+        return Err(format!("{} files failed, more than the {} allowed", failures.len(), max_failures).into());
+    }
+    Ok(())
+}
+
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => panic.downcast_ref::<&str>().map(|message| message.to_string()).unwrap_or_else(|| String::from("panic")),
+    }
+}
+
+// writes the pending batch; if it fails, every file with profiles in it is reported
+async fn write_profiles(sink: &mut Sink, inserts: &mut Vec<DataSchema>, upserts: &mut Vec<DataSchema>, batch_files: &mut Vec<String>, failures: &mut Vec<FileFailure>) {
+    let mut result = sink.write("argo", std::mem::take(inserts), false).await;
+    if result.is_ok() {
+        result = sink.write("argo", std::mem::take(upserts), true).await;
+    }
+    if let Err(e) = result {
+        for file_name in batch_files.iter() {
+            failures.push(FileFailure::new(file_name, "write", &e));
+        }
+    }
+    upserts.clear();
+    batch_files.clear();
+}
+
+// profile files to process: the members of an archive, the index files' entries if any were given,
+// otherwise whatever is in <data_directory>/<wmo>/profiles;
+// also returns the index rows by file, and the ids of every indexed profile, found in the mirror or not
+fn list_profile_files(data_directory: &str, index_files: &[String]) -> Result<(Vec<String>, HashMap<String, index::IndexEntry>, HashSet<String>), Box<dyn Error>> {
+    let mut file_names: Vec<String> = Vec::new();
+    let mut index_entries: HashMap<String, index::IndexEntry> = HashMap::new();
+    let mut indexed_ids: HashSet<String> = HashSet::new();
+    if archive::is_archive(data_directory) {
+        file_names = archive::list_members(data_directory)?;
+    } else if !index_files.is_empty() {
+        for index_file in index_files {
+            for entry in index::read_index(index_file)? {
+                let file_path = index::resolve(data_directory, &entry);
+                indexed_ids.insert(profile_id(file_stem(&file_path), 0));
+                if fs::metadata(&file_path).is_err() {
+                    eprintln!("Indexed file missing from mirror: {}", file_path);
+                    continue;
+                }
+                file_names.push(file_path.clone());
+                index_entries.insert(file_path, entry);
+            }
+        }
+    } else {
+        // an unreadable directory is an error, not an empty mirror, or reconcile would retire everything
+        let entries = fs::read_dir(data_directory).map_err(|e| format!("Can't read {}: {}", data_directory, e))?;
+        for entry in entries {
+            if let Ok(entry) = entry {
+                if let Some(file_name) = entry.file_name().to_str() {
+                    let profile_path = format!("{}/{}/profiles", data_directory, file_name);
+                    if let Ok(profile_entries) = fs::read_dir(profile_path.clone()) {
+                        for profile_entry in profile_entries {
+                            if let Ok(profile_entry) = profile_entry {
+                                if let Some(profile_file_name) = profile_entry.file_name().to_str() {
+                                    let file_path = format!("{}/{}/profiles/{}", data_directory, file_name, profile_file_name);
+                                    file_names.push(file_path);
+                                }
+                            }
+                        }
+                    }   
+                }
+            }
+        }
+    }
+    Ok((file_names, index_entries, indexed_ids))
+}
+
+// moves profiles stored under the ids the first converter wrote, like R4902911_001, onto the id the cycle's R and D
+// files now share, so neither ingest nor reconcile sees them as different profiles; where an R and a D document were
+// both stored, the D one is kept. returns how many documents were moved or removed
+async fn collapse_legacy_ids(sink: &mut Sink) -> Result<usize, Box<dyn Error>> {
+    let mut legacy: HashMap<String, Vec<String>> = HashMap::new();
+    for id in sink.legacy_ids("argo").await? {
+        legacy.entry(collapse_profile_id(&id)).or_default().push(id);
+    }
+    let mut collapsed = 0;
+    for (id, mut old_ids) in legacy {
+        old_ids.sort_by_key(|old_id| !is_delayed_mode(old_id));
+        sink.rekey("argo", &old_ids[0], &id).await?;
+        sink.retire("argo", &old_ids[1..], true).await?;
+        collapsed += old_ids.len();
+    }
+    Ok(collapsed)
+}
+
+// indexes admt_api's queries rely on, as (collection, keys)
+fn api_indexes() -> Vec<(&'static str, bson::Document)> {
+    vec![
+        ("argo", doc! { "geolocation": "2dsphere" }), // $geoWithin polygon searches
+        ("argo", doc! { "JULD": -1 }), // JULD ranges, and the default sort
+        ("argo", doc! { "timestamp": -1 }), // ISO date ranges
+        ("argo", doc! { "STATION_PARAMETERS": 1 }), // $all on the requested parameters
+        ("argo", doc! { "metadata": 1 }),
+        ("argo", doc! { "provenance.converter_version": 1 }), // /provenance
+        ("argoTrajectory", doc! { "PLATFORM_NUMBER": 1, "CYCLE_NUMBER": 1 }),
+        ("argoTechnical", doc! { "PLATFORM_NUMBER": 1, "CYCLE_NUMBER": 1 }),
+    ]
+}
+
+// command line ////////////////////////////////////////////////
+
+struct Options {
+    mode: String,
+    data_directory: String,
+    incremental: bool,
+    index_files: Vec<String>,
+    delete: bool,
+    jobs: usize,
+    batch_size: usize,
+    sink: String,
+    failure_report: Option<String>,
+    max_failures: usize,
+    max_retire: usize,
+    derived: Option<String>,
+    parameter_table: Option<String>,
+}
+
+fn parse_options() -> Options {
+    let mut options = Options {
+        mode: "profiles".to_string(),
+        data_directory: String::new(),
+        incremental: false,
+        index_files: Vec::new(),
+        delete: false,
+        jobs: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        batch_size: 500,
+        sink: String::from("mongo"),
+        failure_report: None,
+        max_failures: 0,
+        max_retire: 10,
+        derived: None,
+        parameter_table: None,
+    };
+    let mut positional: Vec<String> = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--incremental" => options.incremental = true,
+            // repeatable, e.g. once for the core index and once for the BGC index
+            "--index" => options.index_files.push(args.next().expect("--index needs an index file")),
+            "--delete" => options.delete = true,
+            "--jobs" => options.jobs = args.next().and_then(|n| n.parse().ok()).filter(|n| *n > 0).expect("--jobs needs a positive number"),
+            // mongo, ndjson, ndjson:<file> or dir:<directory>
+            "--sink" => options.sink = args.next().expect("--sink needs a sink"),
+            "--batch-size" => options.batch_size = args.next().and_then(|n| n.parse().ok()).filter(|n| *n > 0).expect("--batch-size needs a positive number"),
+            // failures are written as CSV if the file name ends in .csv, JSON otherwise
+            "--failure-report" => options.failure_report = Some(args.next().expect("--failure-report needs a file")),
+            "--max-failures" => options.max_failures = args.next().and_then(|n| n.parse().ok()).expect("--max-failures needs a number"),
+            // the most of the stored profiles, as a percentage, that one reconcile run may retire
+            "--max-retire" => options.max_retire = args.next().and_then(|n| n.parse().ok()).filter(|n| *n <= 100).expect("--max-retire needs a percentage"),
+            // adds TEOS-10 SA, CT, SIGMA0 and DEPTH to profiles with PRES, TEMP, PSAL and a position; SA needs the
+            // Absolute Salinity Anomaly Ratio atlas, gsw_data_v3_0.nc from the TEOS-10 GSW distribution
+            "--derived" => options.derived = Some(args.next().expect("--derived needs the GSW atlas, gsw_data_v3_0.nc")),
+            // the official parameter list for validate to check names against, in place of the built-in table
+            "--parameter-table" => options.parameter_table = Some(args.next().expect("--parameter-table needs a file")),
+            _ => positional.push(arg),
+        }
+    }
+    match positional.as_slice() {
+        // migrate and indexes work on what's stored, so they take no data directory
+        [mode] if ["migrate", "indexes"].contains(&mode.as_str()) => options.mode = mode.clone(),
+        [mode, data_directory] if ["profiles", "meta", "traj", "tech", "reconcile", "validate"].contains(&mode.as_str()) => {
+            options.mode = mode.clone();
+            options.data_directory = data_directory.clone();
+        },
+        [data_directory] => options.data_directory = data_directory.clone(),
+        _ => panic!("Usage: convert_nc [profiles|meta|traj|tech|reconcile|validate] <data_directory> [--incremental] [--index <index_file>]... [--delete] [--jobs N] [--batch-size N] [--sink <sink>] [--failure-report <file>] [--max-failures N] [--max-retire PERCENT] [--derived <gsw_data_v3_0.nc>] [--parameter-table <file>]\n       convert_nc migrate [--batch-size N] [--sink <sink>]\n       convert_nc indexes [--sink <sink>]"),
+    }
+    options
+}
+
+////////////////////////////////////////////////////////////////
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
     
-        argo.insert_one(data_object, None).await?;
+    // Read the command line arguments: an optional mode, then the data_directory
+    // with --index, data_directory is the root of a GDAC mirror, containing dac/
+    // data_directory may also be a .tar or .tar.gz snapshot, whose profiles are read without unpacking it
+    let Options { mode, data_directory, incremental, index_files, delete, jobs, batch_size, sink, failure_report, max_failures, max_retire, derived, parameter_table } = parse_options();
+
+    // validation mode ///////////////////////////////////////////
+
+    if mode == "validate" {
+        if archive::is_archive(&data_directory) {
+            return Err("validate reads files in place, so unpack the archive first".into());
+        }
+        // reports violations of the format rules; nothing is converted or written
+        let parameters = match &parameter_table {
+            Some(parameter_table) => validate::read_parameter_table(parameter_table)?,
+            None => validate::parameter_names(),
+        };
+        let (file_names, _, _) = list_profile_files(&data_directory, &index_files)?;
+        let mut invalid_files = 0;
+        for file_name in &file_names {
+            match validate::validate_profile_file(file_name, &parameters) {
+                Ok(violations) if violations.is_empty() => {},
+                Ok(violations) => {
+                    invalid_files += 1;
+                    println!("{}", file_name);
+                    for violation in violations {
+                        println!("    {}", violation);
+                    }
+                },
+                Err(e) => {
+                    invalid_files += 1;
+                    println!("{}\n    unreadable: {}", file_name, e);
+                }
+            }
+        }
+        eprintln!("{} of {} files have violations", invalid_files, file_names.len());
+        return Ok(());
+    }
+
+    // only profiles are read out of archives; the other modes find their files in a directory tree
+    if ["meta", "traj", "tech"].contains(&mode.as_str()) && archive::is_archive(&data_directory) {
+        return Err(format!("{} mode reads files in place, so unpack the archive first", mode).into());
+    }
+
+    // read before anything is written, so a bad atlas fails the run up front
+    let saar_atlas = match &derived {
+        Some(file_name) if mode == "profiles" => Some(Arc::new(gsw::SaarAtlas::open(file_name).map_err(|e| format!("{}: {}", file_name, e))?)),
+        _ => None,
+    };
+
+    // output setup ////////////////////////////////////////////
+    // documents go to MongoDB unless --sink says otherwise; progress goes to stderr so an ndjson sink can use stdout
+    let mut sink = Sink::open(&sink).await?;
+
+    // migration mode ////////////////////////////////////////////
+
+    if mode == "migrate" {
+        // brings documents written by earlier converters up to the current schema_version
+        let migrations: [(&str, sink::Upgrade); 2] = [("argo", upgrade_profile), ("argoMeta", upgrade_meta)];
+        for (collection, upgrade) in migrations {
+            let (upgraded, failed) = sink.migrate(collection, upgrade, batch_size).await?;
+            eprintln!("{}: {} documents upgraded to schema version {}, {} failed", collection, upgraded, SCHEMA_VERSION, failed);
+        }
+        return sink.finish();
+    }
+
+    // index mode //////////////////////////////////////////////////
+
+    if mode == "indexes" {
+        if !matches!(sink, Sink::Mongo(_)) {
+            return Err("Only a mongo sink has indexes".into());
+        }
+        let created = sink.ensure_indexes(&api_indexes()).await?;
+        eprintln!("{} indexes created, {} already present", created, api_indexes().len() - created);
+        return sink.finish();
+    }
+
+    // every other run makes sure the API's indexes exist, but converting doesn't depend on them;
+    // the geolocation index can't be built over documents from before schema version 2, so migrate first
+    if let Err(e) = sink.ensure_indexes(&api_indexes()).await {
+        eprintln!("{}", e);
+    }
+
+    // a file that fails is reported and passed over, rather than ending the run
+    let mut failures: Vec<FileFailure> = Vec::new();
+
+    // meta file mode //////////////////////////////////////////////
+
+    if mode == "meta" {
+        for file_name in meta::find_meta_files(&data_directory) {
+            eprintln!("Processing file: {}", file_name);
+            let documents = match meta::extract_meta(&file_name) {
+                Ok(documents) => documents,
+                Err(e) => {
+                    failures.push(FileFailure::new(&file_name, "extract", e));
+                    continue;
+                }
+            };
+            // meta files are authoritative, so a rerun replaces what's there
+            if let Err(e) = sink.write("argoMeta", documents, true).await {
+                failures.push(FileFailure::new(&file_name, "write", e));
+            }
+        }
+        sink.finish()?;
+        return report_failures(&failures, failure_report.as_deref(), max_failures);
+    }
+
+    // trajectory file mode ////////////////////////////////////////
+
+    if mode == "traj" {
+        for file_name in traj::find_trajectory_files(&data_directory) {
+            eprintln!("Processing file: {}", file_name);
+            let documents = match traj::extract_trajectory(&file_name) {
+                Ok(documents) => documents,
+                Err(e) => {
+                    failures.push(FileFailure::new(&file_name, "extract", e));
+                    continue;
+                }
+            };
+            if let Err(e) = sink.write("argoTrajectory", documents, true).await {
+                failures.push(FileFailure::new(&file_name, "write", e));
+            }
+        }
+        sink.finish()?;
+        return report_failures(&failures, failure_report.as_deref(), max_failures);
+    }
+
+    // technical file mode /////////////////////////////////////////
+
+    if mode == "tech" {
+        for file_name in tech::find_technical_files(&data_directory) {
+            eprintln!("Processing file: {}", file_name);
+            let documents = match tech::extract_technical(&file_name) {
+                Ok(documents) => documents,
+                Err(e) => {
+                    failures.push(FileFailure::new(&file_name, "extract", e));
+                    continue;
+                }
+            };
+            if let Err(e) = sink.write("argoTechnical", documents, true).await {
+                failures.push(FileFailure::new(&file_name, "write", e));
+            }
+        }
+        sink.finish()?;
+        return report_failures(&failures, failure_report.as_deref(), max_failures);
+    }
+
+    // data unpacking /////////////////////////////////////////////
+
+    // let mut file_names: Vec<String> = Vec::new();
+    // if let Ok(entries) = fs::read_dir(data_directory.clone()) {
+    //     for entry in entries {
+    //         if let Ok(entry) = entry {
+    //             let sub_file_path = format!("{}/{}/profiles", data_directory, entry.file_name().to_str());
+    //             if let Ok(subentries) = fs::read_dir(sub_file_path.clone()) {
+    //                 for subentry in subentries {
+    //                     if let Ok(subentry) = subentry {
+    //                         if let Some(file_name) = subentry.file_name().to_str() {
+    //                             let file_path = format!("{}/{}/profiles/{}", data_directory, entry, file_name);
+    //                             file_names.push(file_path);
+    //                         }
+    //                     }
+    //                 }
+    //             }
+    //         }
+    //     }
+    // }
+    // for file_name in &file_names {
+    //     println!("{}", file_name);
+    // }
+
+    if archive::is_archive(&data_directory) && !index_files.is_empty() {
+        return Err("--index reads a mirror on disk, so it can't be used with an archive".into());
+    }
+    // profiles mode pairs an archive's members as it reads them, so the archive is only decompressed once
+    let (file_names, index_entries, indexed_ids) = if mode == "profiles" && archive::is_archive(&data_directory) {
+        Default::default()
+    } else {
+        list_profile_files(&data_directory, &index_files)?
+    };
+
+    // reconciliation mode /////////////////////////////////////////
+
+    if mode == "reconcile" {
+        // anything stored that no longer appears in the index or source tree is stale
+        let expected: HashSet<String> = if !index_files.is_empty() {
+            indexed_ids
+        } else {
+            file_names.iter().map(|file_name| profile_id(file_stem(file_name), 0)).collect()
+        };
+        if expected.is_empty() {
+            return Err(format!("No profiles found in {}, so nothing was retired", data_directory).into());
+        }
+        let collapsed = collapse_legacy_ids(&mut sink).await?;
+        if collapsed > 0 {
+            eprintln!("{} profiles stored under R or D file ids moved onto their shared id", collapsed);
+        }
+        let live_ids = sink.live_ids("argo").await?;
+        let stale: Vec<String> = live_ids.iter()
+            .filter(|id| !expected.contains(base_id(&collapse_profile_id(id))))
+            .cloned()
+            .collect();
+        // a mirror that's only partly there looks like a mass withdrawal
+        if stale.len() * 100 > live_ids.len() * max_retire {
+            return Err(format!("{} of {} stored profiles look stale, more than the {}% --max-retire allows, so nothing was retired", stale.len(), live_ids.len(), max_retire).into());
+        }
+
+        // tombstoned documents drop out of the API, and are rewritten, clearing deleted, if their file is ingested again
+        sink.retire("argo", &stale, delete).await?;
+        eprintln!("{} stale profiles {}", stale.len(), if delete { "deleted" } else { "tombstoned" });
+        return sink.finish();
+    }
+
+    let mut known_meta_ids: HashSet<String> = HashSet::new(); // argoMeta ids already stored, from this run or an earlier one
+    let (mut new_count, mut updated_count, mut unchanged_count, mut skipped_files) = (0, 0, 0, 0);
+    // argoMeta ids from _meta.nc files, found or not, so each mission is only looked up once
+    let mut mission_docs: HashMap<String, bool> = HashMap::new();
+
+    // so incremental runs compare against, and replace, what the first converter stored under R and D file ids
+    let collapsed = collapse_legacy_ids(&mut sink).await?;
+    if collapsed > 0 {
+        eprintln!("{} profiles stored under R or D file ids moved onto their shared id", collapsed);
+    }
+
+    // tombstoned profiles are rewritten whatever their DATE_UPDATE, and the replacement clears deleted
+    let retired: HashSet<String> = sink.retired_ids("argo").await?.into_iter().collect();
+
+    let started = Instant::now();
+    let (mut file_count, mut profile_count) = (0, 0);
+    let index_entries = Arc::new(index_entries);
+    // an archive is read on its own thread, which hands over each core file and B-file once both are in memory
+    let mut archive_reader = None;
+    let mut pending = if archive::is_archive(&data_directory) {
+        let (sender, receiver) = tokio::sync::mpsc::channel(jobs * 2);
+        let archive_path = data_directory.clone();
+        archive_reader = Some(tokio::task::spawn_blocking(move || archive::stream_pairs(&archive_path, sender)));
+        PendingFiles::Archive(receiver)
+    } else {
+        PendingFiles::Paths(pair_files(file_names).into_iter())
+    };
+    let mut decoding: JoinSet<(String, Result<Vec<(DataSchema, MetaSchema)>, FileFailure>)> = JoinSet::new();
+    let mut inserts: Vec<DataSchema> = Vec::new();
+    let mut upserts: Vec<DataSchema> = Vec::new();
+    let mut batch_files: Vec<String> = Vec::new(); // files with profiles in the pending batch
+
+    loop {
+        // keep up to `jobs` files decoding at once
+        while decoding.len() < jobs {
+            let Some((file, bgc_file)) = pending.next().await else { break };
+            let file_name = file.name.clone();
+
+            // the index's date_update lets unchanged files be skipped without opening them
+            let first_id = profile_id(file_stem(&file_name), 0);
+            if incremental && !retired.contains(&first_id) {
+                let latest_update = [Some(&file), bgc_file.as_ref()].into_iter()
+                    .flatten()
+                    .filter_map(|f| index_entries.get(&f.name))
+                    .map(|entry| entry.date_update.clone())
+                    .max();
+                if let Some(latest_update) = latest_update {
+                    let stored_update = match sink.stored_update(&first_id).await {
+                        Ok(stored_update) => stored_update,
+                        Err(e) => {
+                            failures.push(FileFailure::new(&file_name, "lookup", e));
+                            continue;
+                        }
+                    };
+                    if stored_update.map_or(false, |stored_update| stored_update >= latest_update) {
+                        skipped_files += 1;
+                        continue;
+                    }
+                }
+            }
+
+            let index_entries = Arc::clone(&index_entries);
+            let data_directory = data_directory.clone();
+            let saar_atlas = saar_atlas.clone();
+            decoding.spawn_blocking(move || {
+                // a panic while decoding fails this file, not the run
+                let profiles = std::panic::catch_unwind(AssertUnwindSafe(|| decode_job(&file, bgc_file.as_ref(), &data_directory, &index_entries, saar_atlas.as_deref())))
+                    .unwrap_or_else(|panic| Err(FileFailure::new(&file_name, "extract", panic_message(panic))));
+                (file_name, profiles)
+            });
+        }
+
+        let Some(decoded) = decoding.join_next().await else { break };
+        let (file_name, profiles) = decoded?;
+        let profiles = match profiles {
+            Ok(profiles) => profiles,
+            Err(failure) => {
+                failures.push(failure);
+                continue;
+            }
+        };
+        file_count += 1;
+        profile_count += profiles.len();
+        batch_files.push(file_name.clone());
+
+        for (mut data_object, mut meta_object) in profiles {
+            // in incremental mode, only profiles whose file has a newer DATE_UPDATE get rewritten
+            let mut stored = retired.contains(&data_object._id);
+            if incremental && !stored {
+                let stored_update = match sink.stored_update(&data_object._id).await {
+                    Ok(stored_update) => stored_update,
+                    Err(e) => {
+                        failures.push(FileFailure::new(&file_name, "lookup", e));
+                        continue;
+                    }
+                };
+                if let Some(stored_update) = stored_update {
+                    if stored_update >= data_object.DATE_UPDATE {
+                        unchanged_count += 1;
+                        continue;
+                    }
+                    stored = true;
+                }
+            }
+
+            // prefer the metadata ingested from this float's _meta.nc, when there is one
+            let mission_id = data_object.CONFIG_MISSION_NUMBER.map(|mission| meta::meta_file_id(&meta_object._id, mission)).unwrap_or_default();
+            let has_mission = match mission_docs.get(&mission_id) {
+                Some(found) => *found,
+                None if mission_id.is_empty() => false,
+                None => {
+                    let found = match sink.contains("argoMeta", &mission_id).await {
+                        Ok(found) => found,
+                        Err(e) => {
+                            failures.push(FileFailure::new(&file_name, "metadata", e));
+                            continue;
+                        }
+                    };
+                    mission_docs.insert(mission_id.clone(), found);
+                    found
+                }
+            };
+
+            let meta_id = if has_mission {
+                mission_id
+            } else {
+                // otherwise the id comes from the metadata's content, so identical metadata always lands on the same document
+                let content_id = meta_content_id(&meta_object);
+                if !known_meta_ids.contains(&content_id) {
+                    let stored = match sink.contains("argoMeta", &content_id).await {
+                        Ok(stored) => stored,
+                        Err(e) => {
+                            failures.push(FileFailure::new(&file_name, "metadata", e));
+                            continue;
+                        }
+                    };
+                    if !stored {
+                        meta_object._id = content_id.clone();
+                        if let Err(e) = sink.write("argoMeta", vec![meta_object], true).await {
+                            failures.push(FileFailure::new(&file_name, "metadata", e));
+                            continue;
+                        }
+                    }
+                    known_meta_ids.insert(content_id.clone());
+                }
+                content_id
+            };
+
+            data_object.metadata = vec![meta_id];
+            if stored {
+                upserts.push(data_object);
+                updated_count += 1;
+            } else {
+                inserts.push(data_object);
+                new_count += 1;
+            }
+        }
+
+        if inserts.len() >= batch_size || upserts.len() >= batch_size {
+            write_profiles(&mut sink, &mut inserts, &mut upserts, &mut batch_files, &mut failures).await;
+        }
+    }
+    write_profiles(&mut sink, &mut inserts, &mut upserts, &mut batch_files, &mut failures).await;
+    if let Some(archive_reader) = archive_reader {
+        if let Err(e) = archive_reader.await? {
+            failures.push(FileFailure::new(&data_directory, "read", e));
+        }
+    }
+
+    let elapsed = started.elapsed().as_secs_f64();
+    eprintln!("{} files, {} profiles in {:.1}s ({:.1} profiles/s)", file_count, profile_count, elapsed, profile_count as f64 / elapsed.max(f64::EPSILON));
+
+    eprintln!("{} new, {} updated, {} unchanged profiles", new_count, updated_count, unchanged_count);
+    if skipped_files > 0 {
+        eprintln!("{} files skipped as unchanged in the index", skipped_files);
     }
     
-    Ok(())
+    sink.finish()?;
+    report_failures(&failures, failure_report.as_deref(), max_failures)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id_for(file_name: &str, pindex: usize) -> String {
+        profile_id(file_stem(file_name), pindex)
+    }
+
+    #[test]
+    fn file_stems() {
+        assert_eq!(file_stem("dac/aoml/4902911/profiles/R4902911_001.nc"), "R4902911_001");
+        assert_eq!(file_stem("dac/aoml/4902911/profiles/D4902911_001D.nc.gz"), "D4902911_001D");
+        assert_eq!(file_stem("snapshot.tar.gz/dac/coriolis/6901580/profiles/BR6901580_012.nc"), "BR6901580_012");
+    }
+
+    #[test]
+    fn realtime_and_delayed_files_share_an_id() {
+        let path = "dac/aoml/4902911/profiles/";
+        for prefix in ["R", "D"] {
+            assert_eq!(id_for(&format!("{}{}4902911_001.nc", path, prefix), 0), "4902911_001");
+            assert_eq!(id_for(&format!("{}{}4902911_001.nc.gz", path, prefix), 0), "4902911_001");
+            assert_eq!(id_for(&format!("{}{}4902911_001D.nc", path, prefix), 0), "4902911_001D");
+        }
+    }
 
+    #[test]
+    fn bgc_and_synthetic_files_keep_their_prefix() {
+        let path = "dac/coriolis/6901580/profiles/";
+        for prefix in ["BR", "BD"] {
+            assert_eq!(id_for(&format!("{}{}6901580_012.nc", path, prefix), 0), "B6901580_012");
+            assert_eq!(id_for(&format!("{}{}6901580_012D.nc.gz", path, prefix), 0), "B6901580_012D");
+        }
+        for prefix in ["SR", "SD", "S"] {
+            assert_eq!(id_for(&format!("{}{}6901580_012.nc", path, prefix), 0), "S6901580_012");
+        }
+        assert_eq!(id_for("dac/coriolis/6901580/6901580_Sprof.nc", 0), "6901580_Sprof");
+    }
+
+    #[test]
+    fn multi_profile_ids_and_their_base() {
+        let cases = [
+            ("dac/aoml/4902911/profiles/R4902911_001.nc", "4902911_001"),
+            ("dac/aoml/4902911/profiles/D4902911_001D.nc.gz", "4902911_001D"),
+            ("dac/coriolis/6901580/profiles/BD6901580_012.nc", "B6901580_012"),
+            ("dac/coriolis/6901580/profiles/SD6901580_012D.nc", "S6901580_012D"),
+            ("dac/coriolis/6901580/6901580_Sprof.nc", "6901580_Sprof"),
+        ];
+        for (file_name, id) in cases {
+            assert_eq!(id_for(file_name, 0), id);
+            assert_eq!(id_for(file_name, 2), format!("{}_2", id));
+            assert_eq!(base_id(&id_for(file_name, 0)), id);
+            assert_eq!(base_id(&id_for(file_name, 2)), id);
+            assert_eq!(base_id(&id_for(file_name, 12)), id);
+        }
+    }
+
+    #[test]
+    fn legacy_ids_collapse_onto_current_ones() {
+        let cases = [
+            ("R4902911_001", "4902911_001"),
+            ("D4902911_001_2", "4902911_001_2"),
+            ("BD6901580_012", "B6901580_012"),
+            ("SR6901580_012D", "S6901580_012D"),
+            ("4902911_001", "4902911_001"),
+            ("6901580_Sprof", "6901580_Sprof"),
+        ];
+        for (legacy_id, id) in cases {
+            assert_eq!(collapse_profile_id(legacy_id), id);
+        }
+        // what reconcile compares against the expected ids
+        assert_eq!(base_id(&collapse_profile_id("R4902911_001_1")), "4902911_001");
+    }
+
+    #[test]
+    fn core_and_bgc_files_pair_up() {
+        let key = pairing_key("dac/aoml/4902911/profiles/D4902911_001.nc");
+        assert_eq!(pairing_key("dac/aoml/4902911/profiles/R4902911_001.nc"), key);
+        assert_eq!(pairing_key("dac/aoml/4902911/profiles/BR4902911_001.nc"), key);
+        assert_eq!(pairing_key("dac/aoml/4902911/profiles/BD4902911_001.nc.gz"), key);
+        assert_ne!(pairing_key("dac/aoml/4902911/profiles/BD4902911_001D.nc"), key);
+        assert_eq!(pairing_key("dac/aoml/4902911/profiles/BD4902911_001D.nc"), pairing_key("dac/aoml/4902911/profiles/R4902911_001D.nc.gz"));
+    }
+}
@@ -0,0 +1,148 @@
+// unpacking for <wmo>_meta.nc files, the GDAC's authoritative float metadata
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+use argo_schema::{MetaSchema, SensorRecord, SCHEMA_VERSION};
+
+use crate::{latitude, longitude, unpack_optional_value, unpack_optional_values, unpack_string, unpack_string_array, split_string};
+
+fn strlen(name: &str, file: &netcdf::File) -> usize {
+    file.variable(name)
+        .and_then(|variable| variable.dimensions().last().map(|d| d.len()))
+        .unwrap_or(0)
+}
+
+fn meta_string(name: &str, file: &netcdf::File) -> String {
+    let len = strlen(name, file);
+    unpack_string(name, len, [0..len].into(), file)
+}
+
+fn meta_string_array(name: &str, arraydim: usize, file: &netcdf::File) -> Vec<String> {
+    let len = strlen(name, file);
+    if len == 0 {
+        return vec![String::new(); arraydim];
+    }
+    unpack_string_array(name, len, arraydim, [0..arraydim, 0..len].into(), file)
+}
+
+fn dimension(name: &str, file: &netcdf::File) -> usize {
+    file.dimension(name).map(|d| d.len()).unwrap_or(0)
+}
+
+pub fn meta_file_id(PLATFORM_NUMBER: &str, CONFIG_MISSION_NUMBER: i32) -> String {
+    format!("{}_mission{}", PLATFORM_NUMBER, CONFIG_MISSION_NUMBER)
+}
+
+pub fn find_meta_files(data_directory: &str) -> Vec<String> {
+    // meta files sit next to the profiles directory, at <data_directory>/<wmo>/<wmo>_meta.nc
+    let mut file_names: Vec<String> = Vec::new();
+    if let Ok(entries) = fs::read_dir(data_directory) {
+        for entry in entries {
+            if let Ok(entry) = entry {
+                if let Some(wmo) = entry.file_name().to_str() {
+                    let file_path = format!("{}/{}/{}_meta.nc", data_directory, wmo, wmo);
+                    if fs::metadata(&file_path).is_ok() {
+                        file_names.push(file_path);
+                    }
+                }
+            }
+        }
+    }
+    file_names
+}
+
+// one argoMeta document per configuration mission, so profiles can link on PLATFORM_NUMBER and CONFIG_MISSION_NUMBER
+pub fn extract_meta(file_name: &str) -> Result<Vec<MetaSchema>, Box<dyn Error>> {
+    let file = netcdf::open(file_name)?;
+    let N_SENSOR: usize = dimension("N_SENSOR", &file);
+    let N_POSITIONING_SYSTEM: usize = dimension("N_POSITIONING_SYSTEM", &file);
+    let N_LAUNCH_CONFIG_PARAM: usize = dimension("N_LAUNCH_CONFIG_PARAM", &file);
+    let N_CONFIG_PARAM: usize = dimension("N_CONFIG_PARAM", &file);
+    let N_MISSIONS: usize = dimension("N_MISSIONS", &file);
+
+    let PLATFORM_NUMBER: String = meta_string("PLATFORM_NUMBER", &file);
+
+    let sensors: Vec<SensorRecord> = meta_string_array("SENSOR", N_SENSOR, &file).into_iter()
+        .zip(meta_string_array("SENSOR_MAKER", N_SENSOR, &file))
+        .zip(meta_string_array("SENSOR_MODEL", N_SENSOR, &file))
+        .zip(meta_string_array("SENSOR_SERIAL_NO", N_SENSOR, &file))
+        .map(|(((SENSOR, SENSOR_MAKER), SENSOR_MODEL), SENSOR_SERIAL_NO)| SensorRecord {
+            SENSOR,
+            SENSOR_MAKER,
+            SENSOR_MODEL,
+            SENSOR_SERIAL_NO,
+        })
+        .collect();
+
+    let launch_config_names = meta_string_array("LAUNCH_CONFIG_PARAMETER_NAME", N_LAUNCH_CONFIG_PARAM, &file);
+    let launch_config_values: Vec<Option<f64>> = match file.variable("LAUNCH_CONFIG_PARAMETER_VALUE") {
+        Some(variable) => unpack_optional_values(&variable, [0..N_LAUNCH_CONFIG_PARAM].into())?,
+        None => vec![],
+    };
+    let launch_config: HashMap<String, f64> = launch_config_names.into_iter()
+        .zip(launch_config_values)
+        .filter_map(|(name, value)| value.filter(|_| !name.is_empty()).map(|value| (name, value)))
+        .collect();
+
+    let platform = MetaSchema {
+        _id: PLATFORM_NUMBER.clone(),
+        schema_version: SCHEMA_VERSION,
+        DATA_TYPE: meta_string("DATA_TYPE", &file),
+        FORMAT_VERSION: meta_string("FORMAT_VERSION", &file),
+        HANDBOOK_VERSION: meta_string("HANDBOOK_VERSION", &file),
+        REFERENCE_DATE_TIME: String::new(), // not carried by meta files
+        PROJECT_NAME: meta_string("PROJECT_NAME", &file),
+        PI_NAME: split_string(meta_string("PI_NAME", &file), ','),
+        DATA_CENTRE: meta_string("DATA_CENTRE", &file),
+        PLATFORM_TYPE: meta_string("PLATFORM_TYPE", &file),
+        FLOAT_SERIAL_NO: meta_string("FLOAT_SERIAL_NO", &file),
+        FIRMWARE_VERSION: meta_string("FIRMWARE_VERSION", &file),
+        WMO_INST_TYPE: meta_string("WMO_INST_TYPE", &file),
+        POSITIONING_SYSTEM: meta_string_array("POSITIONING_SYSTEM", N_POSITIONING_SYSTEM, &file).join(","),
+        PLATFORM_NUMBER: Some(PLATFORM_NUMBER.clone()),
+        PLATFORM_MAKER: Some(meta_string("PLATFORM_MAKER", &file)),
+        BATTERY_TYPE: Some(meta_string("BATTERY_TYPE", &file)),
+        BATTERY_PACKS: Some(meta_string("BATTERY_PACKS", &file)),
+        LAUNCH_DATE: Some(meta_string("LAUNCH_DATE", &file)),
+        LAUNCH_LATITUDE: latitude(unpack_optional_value("LAUNCH_LATITUDE", (..).into(), &file)),
+        LAUNCH_LONGITUDE: longitude(unpack_optional_value("LAUNCH_LONGITUDE", (..).into(), &file)),
+        LAUNCH_QC: Some(meta_string("LAUNCH_QC", &file)),
+        START_DATE: Some(meta_string("START_DATE", &file)),
+        END_MISSION_DATE: Some(meta_string("END_MISSION_DATE", &file)),
+        END_MISSION_STATUS: Some(meta_string("END_MISSION_STATUS", &file)),
+        sensors: Some(sensors),
+        launch_config: Some(launch_config),
+        ..Default::default()
+    };
+
+    let config_names = meta_string_array("CONFIG_PARAMETER_NAME", N_CONFIG_PARAM, &file);
+    let CONFIG_MISSION_COMMENT = meta_string_array("CONFIG_MISSION_COMMENT", N_MISSIONS, &file);
+    let mut missions: Vec<MetaSchema> = Vec::new();
+    for mindex in 0..N_MISSIONS {
+        // profiles find their mission's document by number, so a mission without one can't be stored
+        let Some(CONFIG_MISSION_NUMBER) = unpack_optional_value("CONFIG_MISSION_NUMBER", [mindex..mindex+1].into(), &file).map(|mission| mission as i32) else {
+            eprintln!("{}: mission {} has no CONFIG_MISSION_NUMBER, skipping it", file_name, mindex);
+            continue;
+        };
+        let config_values: Vec<Option<f64>> = match file.variable("CONFIG_PARAMETER_VALUE") {
+            Some(variable) => unpack_optional_values(&variable, [mindex..mindex+1, 0..N_CONFIG_PARAM].into())?,
+            None => vec![],
+        };
+        let config: HashMap<String, f64> = config_names.iter()
+            .cloned()
+            .zip(config_values)
+            .filter_map(|(name, value)| value.filter(|_| !name.is_empty()).map(|value| (name, value)))
+            .collect();
+
+        let mut mission = platform.clone();
+        mission._id = meta_file_id(&PLATFORM_NUMBER, CONFIG_MISSION_NUMBER);
+        mission.CONFIG_MISSION_NUMBER = Some(CONFIG_MISSION_NUMBER);
+        mission.CONFIG_MISSION_COMMENT = CONFIG_MISSION_COMMENT.get(mindex).cloned();
+        mission.config = Some(config);
+        missions.push(mission);
+    }
+
+    Ok(missions)
+}
@@ -0,0 +1,389 @@
+// where converted documents end up: the argo MongoDB database, newline-delimited JSON,
+// or a directory holding one JSON file per document
+
+use std::collections::HashSet;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{BufWriter, Write};
+
+use mongodb::bson::{doc, Bson, Document, Regex};
+use mongodb::options::{ClientOptions, FindOneOptions, FindOptions, InsertManyOptions, ResolverConfig};
+use mongodb::{Client, IndexModel};
+use argo_schema::{collapse_profile_id, SCHEMA_VERSION};
+use serde::Serialize;
+
+// MongoDB's 16 MB document limit, less room for the rest of an update command
+const MAX_COMMAND_BYTES: usize = 16 * 1024 * 1024 - 64 * 1024;
+
+// one of argo_schema::migrate's upgrade functions
+pub type Upgrade = fn(&mut Document) -> Result<bool, Box<dyn Error>>;
+
+pub enum Sink {
+    // the argo database at MONGODB_URI
+    Mongo(Client),
+    // one {"collection": ..., "document": ...} object per line, to stdout or a file
+    Ndjson(Box<dyn Write + Send>),
+    // <root>/<collection>/<_id>.json
+    Directory(String),
+}
+
+#[derive(Serialize)]
+struct NdjsonLine<'a, T> {
+    collection: &'a str,
+    document: &'a T,
+}
+
+fn document_path(root: &str, collection: &str, id: &str) -> String {
+    format!("{}/{}/{}.json", root, collection, id)
+}
+
+fn read_document(root: &str, collection: &str, id: &str) -> Result<Option<serde_json::Value>, Box<dyn Error>> {
+    match fs::read(document_path(root, collection, id)) {
+        Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl Sink {
+    // mongo, ndjson (stdout), ndjson:<file> or dir:<directory>
+    pub async fn open(spec: &str) -> Result<Sink, Box<dyn Error>> {
+        match spec.split_once(':') {
+            None if spec == "mongo" => {
+                // Load the MongoDB connection string from an environment variable:
+                let client_uri =
+                   env::var("MONGODB_URI").expect("You must set the MONGODB_URI environment var!");
+
+                // A Client is needed to connect to MongoDB:
+                // An extra line of code to work around a DNS issue on Windows:
+                let options =
+                   ClientOptions::parse_with_resolver_config(&client_uri, ResolverConfig::cloudflare())
+                      .await?;
+                Ok(Sink::Mongo(Client::with_options(options)?))
+            },
+            None if spec == "ndjson" => Ok(Sink::Ndjson(Box::new(BufWriter::new(std::io::stdout())))),
+            Some(("ndjson", path)) => Ok(Sink::Ndjson(Box::new(BufWriter::new(fs::File::create(path)?)))),
+            Some(("dir", path)) => {
+                fs::create_dir_all(path)?;
+                Ok(Sink::Directory(path.to_string()))
+            },
+            _ => Err(format!("Unknown sink {}, expected mongo, ndjson, ndjson:<file> or dir:<directory>", spec).into()),
+        }
+    }
+
+    // DATE_UPDATE of a stored profile, for incremental runs; a stream has nothing to compare against.
+    // tombstoned profiles keep theirs, so callers check retired_ids first
+    pub async fn stored_update(&self, id: &str) -> Result<Option<String>, Box<dyn Error>> {
+        match self {
+            Sink::Mongo(client) => {
+                let options = FindOneOptions::builder().projection(doc! { "DATE_UPDATE": 1 }).build();
+                let document = client.database("argo").collection::<Document>("argo").find_one(doc! { "_id": id }, options).await?;
+                Ok(document.and_then(|document| document.get_str("DATE_UPDATE").ok().map(String::from)))
+            },
+            Sink::Ndjson(_) => Ok(None),
+            Sink::Directory(root) => {
+                let document = read_document(root, "argo", id)?;
+                Ok(document.and_then(|document| document.get("DATE_UPDATE")?.as_str().map(String::from)))
+            },
+        }
+    }
+
+    pub async fn contains(&self, collection: &str, id: &str) -> Result<bool, Box<dyn Error>> {
+        match self {
+            Sink::Mongo(client) => {
+                let options = FindOneOptions::builder().projection(doc! { "_id": 1 }).build();
+                Ok(client.database("argo").collection::<Document>(collection).find_one(doc! { "_id": id }, options).await?.is_some())
+            },
+            Sink::Ndjson(_) => Ok(false),
+            Sink::Directory(root) => Ok(fs::metadata(document_path(root, collection, id)).is_ok()),
+        }
+    }
+
+    // with replace, documents overwrite whatever shares their _id; otherwise they're expected to be new
+    pub async fn write<T: Serialize>(&mut self, collection: &str, documents: Vec<T>, replace: bool) -> Result<(), Box<dyn Error>> {
+        if documents.is_empty() {
+            return Ok(());
+        }
+        match self {
+            Sink::Mongo(client) => {
+                let documents = documents.iter()
+                    .map(mongodb::bson::to_document)
+                    .collect::<Result<Vec<Document>, _>>()?;
+                if replace {
+                    // the update command is one BSON document, so a batch of large profiles is split over several
+                    let mut commands: Vec<Vec<Document>> = vec![Vec::new()];
+                    let mut command_bytes = 0;
+                    for document in documents {
+                        let update = doc! {
+                            "q": { "_id": document.get("_id").cloned() },
+                            "u": document,
+                            "upsert": true,
+                        };
+                        let update_bytes = mongodb::bson::to_vec(&update)?.len();
+                        if command_bytes + update_bytes > MAX_COMMAND_BYTES && commands.last().is_some_and(|updates| !updates.is_empty()) {
+                            commands.push(Vec::new());
+                            command_bytes = 0;
+                        }
+                        command_bytes += update_bytes;
+                        commands.last_mut().unwrap().push(update);
+                    }
+                    let mut errors: Vec<Bson> = Vec::new();
+                    for updates in commands {
+                        let result = client.database("argo").run_command(doc! { "update": collection, "updates": updates, "ordered": false }, None).await?;
+                        if let Ok(command_errors) = result.get_array("writeErrors") {
+                            errors.extend(command_errors.iter().cloned());
+                        }
+                    }
+                    if !errors.is_empty() {
+                        return Err(format!("{} upserts failed: {:?}", errors.len(), errors.first()).into());
+                    }
+                } else {
+                    let options = InsertManyOptions::builder().ordered(false).build();
+                    client.database("argo").collection::<Document>(collection).insert_many(documents, options).await?;
+                }
+            },
+            Sink::Ndjson(out) => {
+                for document in &documents {
+                    serde_json::to_writer(&mut *out, &NdjsonLine { collection, document })?;
+                    writeln!(out)?;
+                }
+            },
+            Sink::Directory(root) => {
+                fs::create_dir_all(format!("{}/{}", root, collection))?;
+                for document in &documents {
+                    let value = serde_json::to_value(document)?;
+                    let id = value.get("_id").and_then(|id| id.as_str()).ok_or("Document has no string _id")?;
+                    fs::write(document_path(root, collection, id), serde_json::to_vec_pretty(&value)?)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // ids of the documents in a collection that haven't been tombstoned
+    pub async fn live_ids(&self, collection: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        if let Sink::Ndjson(_) = self {
+            return Err("An ndjson sink can't be read back".into());
+        }
+        self.ids(collection, false).await
+    }
+
+    // ids of tombstoned documents; a stream holds none
+    pub async fn retired_ids(&self, collection: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        if let Sink::Ndjson(_) = self {
+            return Ok(Vec::new());
+        }
+        self.ids(collection, true).await
+    }
+
+    async fn ids(&self, collection: &str, retired: bool) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut ids: Vec<String> = Vec::new();
+        match self {
+            Sink::Mongo(client) => {
+                let options = FindOptions::builder().projection(doc! { "_id": 1 }).build();
+                let filter = if retired { doc! { "deleted": true } } else { doc! { "deleted": { "$ne": true } } };
+                let mut cursor = client.database("argo").collection::<Document>(collection).find(filter, options).await?;
+                while cursor.advance().await? {
+                    ids.push(cursor.current().get_str("_id")?.to_string());
+                }
+            },
+            Sink::Ndjson(_) => {},
+            Sink::Directory(root) => {
+                if let Ok(entries) = fs::read_dir(format!("{}/{}", root, collection)) {
+                    for entry in entries {
+                        let file_name = entry?.file_name();
+                        if let Some(id) = file_name.to_str().and_then(|f| f.strip_suffix(".json")) {
+                            let deleted = read_document(root, collection, id)?
+                                .and_then(|document| document.get("deleted")?.as_bool())
+                                .unwrap_or(false);
+                            if deleted == retired {
+                                ids.push(id.to_string());
+                            }
+                        }
+                    }
+                }
+            },
+        }
+        Ok(ids)
+    }
+
+    // profile ids still in the form the first converter wrote, the file stem with its R or D data mode letter;
+    // a stream holds none
+    pub async fn legacy_ids(&self, collection: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut ids: Vec<String> = Vec::new();
+        match self {
+            Sink::Mongo(client) => {
+                // anchored prefixes, so the _id index does the work
+                let prefixes: Vec<Bson> = ["R", "D", "BR", "BD", "SR", "SD"].iter()
+                    .map(|prefix| Bson::RegularExpression(Regex { pattern: format!("^{}", prefix), options: String::new() }))
+                    .collect();
+                let options = FindOptions::builder().projection(doc! { "_id": 1 }).build();
+                let mut cursor = client.database("argo").collection::<Document>(collection).find(doc! { "_id": { "$in": prefixes } }, options).await?;
+                while cursor.advance().await? {
+                    ids.push(cursor.current().get_str("_id")?.to_string());
+                }
+            },
+            Sink::Ndjson(_) => {},
+            Sink::Directory(root) => {
+                if let Ok(entries) = fs::read_dir(format!("{}/{}", root, collection)) {
+                    for entry in entries {
+                        if let Some(id) = entry?.file_name().to_str().and_then(|f| f.strip_suffix(".json")) {
+                            ids.push(id.to_string());
+                        }
+                    }
+                }
+            },
+        }
+        ids.retain(|id| collapse_profile_id(id) != *id);
+        Ok(ids)
+    }
+
+    // moves a document to a new _id, which can't be changed in place; a document already stored under the new id
+    // is kept, and the old one just removed
+    pub async fn rekey(&mut self, collection: &str, old_id: &str, new_id: &str) -> Result<(), Box<dyn Error>> {
+        let moved = !self.contains(collection, new_id).await?;
+        match self {
+            Sink::Mongo(client) => {
+                let documents = client.database("argo").collection::<Document>(collection);
+                if moved {
+                    if let Some(mut document) = documents.find_one(doc! { "_id": old_id }, None).await? {
+                        document.insert("_id", new_id);
+                        documents.insert_one(document, None).await?;
+                    }
+                }
+                documents.delete_one(doc! { "_id": old_id }, None).await?;
+            },
+            Sink::Ndjson(_) => return Err("An ndjson sink can't be modified".into()),
+            Sink::Directory(root) => {
+                if moved {
+                    if let Some(mut document) = read_document(root, collection, old_id)? {
+                        document["_id"] = serde_json::Value::String(new_id.to_string());
+                        fs::write(document_path(root, collection, new_id), serde_json::to_vec_pretty(&document)?)?;
+                    }
+                }
+                fs::remove_file(document_path(root, collection, old_id))?;
+            },
+        }
+        Ok(())
+    }
+
+    // tombstones documents by setting deleted: true, or removes them outright
+    pub async fn retire(&mut self, collection: &str, ids: &[String], delete: bool) -> Result<(), Box<dyn Error>> {
+        match self {
+            Sink::Mongo(client) => {
+                let documents = client.database("argo").collection::<Document>(collection);
+                for ids in ids.chunks(1000) {
+                    if delete {
+                        documents.delete_many(doc! { "_id": { "$in": ids } }, None).await?;
+                    } else {
+                        documents.update_many(doc! { "_id": { "$in": ids } }, doc! { "$set": { "deleted": true } }, None).await?;
+                    }
+                }
+            },
+            Sink::Ndjson(_) => return Err("An ndjson sink can't be modified".into()),
+            Sink::Directory(root) => {
+                for id in ids {
+                    if delete {
+                        fs::remove_file(document_path(root, collection, id))?;
+                    } else if let Some(mut document) = read_document(root, collection, id)? {
+                        document["deleted"] = serde_json::Value::Bool(true);
+                        fs::write(document_path(root, collection, id), serde_json::to_vec_pretty(&document)?)?;
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // rewrites every document older than SCHEMA_VERSION, returning how many were upgraded and how many couldn't be;
+    // only a MongoDB sink holds documents to migrate. an upgrade that changes the _id stores the document under the
+    // new id and removes the old one, unless a document already has the new id: then the old one is just removed.
+    // going in _id order, a cycle's D document comes before its R one, so it's the one kept
+    pub async fn migrate(&mut self, collection: &str, upgrade: Upgrade, batch_size: usize) -> Result<(usize, usize), Box<dyn Error>> {
+        let client = match self {
+            Sink::Mongo(client) => client.clone(),
+            _ => return Err("Only a mongo sink can be migrated".into()),
+        };
+        let filter = doc! { "$or": [
+            { "schema_version": { "$exists": false } },
+            { "schema_version": { "$lt": SCHEMA_VERSION } },
+        ] };
+        let options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+        let mut cursor = client.database("argo").collection::<Document>(collection).find(filter, options).await?;
+        let (mut upgraded, mut failed) = (0, 0);
+        let mut batch: Vec<Document> = Vec::new();
+        // ids given up by rekeyed documents, removed once their replacements are written
+        let mut old_ids: Vec<String> = Vec::new();
+        let mut new_ids: HashSet<String> = HashSet::new();
+        while cursor.advance().await? {
+            let mut document = cursor.deserialize_current()?;
+            let old_id = document.get_str("_id").ok().map(String::from);
+            match upgrade(&mut document) {
+                Ok(true) => {
+                    let new_id = document.get_str("_id").ok().map(String::from);
+                    match (old_id, new_id) {
+                        (Some(old_id), Some(new_id)) if old_id != new_id => {
+                            if !new_ids.contains(&new_id) && !self.contains(collection, &new_id).await? {
+                                new_ids.insert(new_id);
+                                batch.push(document);
+                            }
+                            old_ids.push(old_id);
+                        },
+                        _ => batch.push(document),
+                    }
+                },
+                Ok(false) => {},
+                Err(e) => {
+                    eprintln!("Couldn't migrate {} {:?}: {}", collection, document.get("_id"), e);
+                    failed += 1;
+                }
+            }
+            if batch.len() >= batch_size {
+                upgraded += batch.len();
+                self.write(collection, std::mem::take(&mut batch), true).await?;
+                self.retire(collection, &std::mem::take(&mut old_ids), true).await?;
+            }
+        }
+        upgraded += batch.len();
+        self.write(collection, batch, true).await?;
+        self.retire(collection, &old_ids, true).await?;
+        Ok((upgraded, failed))
+    }
+
+    // creates whichever of the indexes are missing, reporting each one, and returns how many were created;
+    // only meaningful for MongoDB, the other sinks have nothing to index
+    pub async fn ensure_indexes(&self, indexes: &[(&str, Document)]) -> Result<usize, Box<dyn Error>> {
+        let client = match self {
+            Sink::Mongo(client) => client,
+            _ => return Ok(0),
+        };
+        let mut created = 0;
+        let mut failures: Vec<String> = Vec::new();
+        for (collection, keys) in indexes {
+            let documents = client.database("argo").collection::<Document>(collection);
+            // a collection that doesn't exist yet has no indexes
+            let existing = documents.list_index_names().await.unwrap_or_default();
+            let index = IndexModel::builder().keys(keys.clone()).build();
+            match documents.create_index(index, None).await {
+                Ok(result) if !existing.contains(&result.index_name) => {
+                    eprintln!("Created index {} on {}", result.index_name, collection);
+                    created += 1;
+                },
+                Ok(_) => {},
+                Err(e) => failures.push(format!("{} on {}: {}", keys, collection, e)),
+            }
+        }
+        if !failures.is_empty() {
+            return Err(format!("Couldn't create {} indexes: {}", failures.len(), failures.join("; ")).into());
+        }
+        Ok(created)
+    }
+
+    pub fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Sink::Ndjson(out) = self {
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
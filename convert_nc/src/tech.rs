@@ -0,0 +1,68 @@
+// unpacking for <wmo>_tech.nc technical files
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+
+use argo_schema::TechnicalSchema;
+
+use crate::{unpack_optional_values, unpack_string, unpack_string_array};
+
+pub fn find_technical_files(data_directory: &str) -> Vec<String> {
+    // technical files sit next to the profiles directory, at <data_directory>/<wmo>/<wmo>_tech.nc
+    let mut file_names: Vec<String> = Vec::new();
+    if let Ok(entries) = fs::read_dir(data_directory) {
+        for entry in entries {
+            if let Ok(entry) = entry {
+                if let Some(wmo) = entry.file_name().to_str() {
+                    let file_path = format!("{}/{}/{}_tech.nc", data_directory, wmo, wmo);
+                    if fs::metadata(&file_path).is_ok() {
+                        file_names.push(file_path);
+                    }
+                }
+            }
+        }
+    }
+    file_names
+}
+
+pub fn extract_technical(file_name: &str) -> Result<Vec<TechnicalSchema>, Box<dyn Error>> {
+    let file = netcdf::open(file_name)?;
+    let N_TECH_PARAM: usize = file.dimension("N_TECH_PARAM").map(|d| d.len()).unwrap_or(0);
+    let STRING128: usize = 128;
+
+    let PLATFORM_NUMBER: String = unpack_string("PLATFORM_NUMBER", 8, [0..8].into(), &file);
+    let TECHNICAL_PARAMETER_NAME: Vec<String> = unpack_string_array("TECHNICAL_PARAMETER_NAME", STRING128, N_TECH_PARAM, [0..N_TECH_PARAM, 0..STRING128].into(), &file);
+    let TECHNICAL_PARAMETER_VALUE: Vec<String> = unpack_string_array("TECHNICAL_PARAMETER_VALUE", STRING128, N_TECH_PARAM, [0..N_TECH_PARAM, 0..STRING128].into(), &file);
+    let CYCLE_NUMBER: Vec<Option<i32>> = match file.variable("CYCLE_NUMBER") {
+        Some(variable) => unpack_optional_values(&variable, [0..N_TECH_PARAM].into())?.into_iter().map(|cycle| cycle.map(|cycle| cycle as i32)).collect(),
+        None => vec![None; N_TECH_PARAM],
+    };
+
+    let mut cycles: BTreeMap<i32, TechnicalSchema> = BTreeMap::new();
+    for i in 0..N_TECH_PARAM {
+        let name = &TECHNICAL_PARAMETER_NAME[i];
+        let value = &TECHNICAL_PARAMETER_VALUE[i];
+        let Some(cycle_number) = CYCLE_NUMBER[i] else { continue };
+        if name.is_empty() {
+            continue;
+        }
+        let cycle = cycles.entry(cycle_number).or_insert_with(|| TechnicalSchema {
+            _id: format!("{}_{:03}", PLATFORM_NUMBER, cycle_number),
+            PLATFORM_NUMBER: PLATFORM_NUMBER.clone(),
+            CYCLE_NUMBER: cycle_number,
+            parameters: HashMap::new(),
+            text_parameters: HashMap::new(),
+        });
+        match value.parse::<f64>() {
+            Ok(number) if number.is_finite() => {
+                cycle.parameters.insert(name.clone(), number);
+            },
+            _ => {
+                cycle.text_parameters.insert(name.clone(), value.clone());
+            }
+        }
+    }
+
+    Ok(cycles.into_values().collect())
+}
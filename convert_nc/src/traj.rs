@@ -0,0 +1,156 @@
+// unpacking for <wmo>_Rtraj.nc / <wmo>_Dtraj.nc trajectory files
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+
+use argo_schema::{DataMode, QcFlag, TrajectoryMeasurement, TrajectorySchema};
+
+use crate::{latitude, longitude, unpack_optional_values, unpack_string, unpack_string_array};
+
+// per-cycle timing variables, all dimensioned [N_CYCLE]
+const CYCLE_TIMES: [&str; 16] = [
+    "JULD_DESCENT_START",
+    "JULD_FIRST_STABILIZATION",
+    "JULD_DESCENT_END",
+    "JULD_PARK_START",
+    "JULD_PARK_END",
+    "JULD_DEEP_DESCENT_END",
+    "JULD_DEEP_PARK_START",
+    "JULD_ASCENT_START",
+    "JULD_DEEP_ASCENT_START",
+    "JULD_ASCENT_END",
+    "JULD_TRANSMISSION_START",
+    "JULD_FIRST_MESSAGE",
+    "JULD_FIRST_LOCATION",
+    "JULD_LAST_LOCATION",
+    "JULD_LAST_MESSAGE",
+    "JULD_TRANSMISSION_END",
+];
+
+fn dimension(name: &str, file: &netcdf::File) -> usize {
+    file.dimension(name).map(|d| d.len()).unwrap_or(0)
+}
+
+// a one-dimensional variable with its _FillValue mapped to None; all None if the file doesn't have it
+fn values(name: &str, len: usize, file: &netcdf::File) -> Result<Vec<Option<f64>>, Box<dyn Error>> {
+    match file.variable(name) {
+        Some(variable) => unpack_optional_values(&variable, [0..len].into()),
+        None => Ok(vec![None; len]),
+    }
+}
+
+fn integer_values(name: &str, len: usize, file: &netcdf::File) -> Result<Vec<Option<i32>>, Box<dyn Error>> {
+    Ok(values(name, len, file)?.into_iter().map(|value| value.map(|value| value as i32)).collect())
+}
+
+pub fn find_trajectory_files(data_directory: &str) -> Vec<String> {
+    // trajectory files sit next to the profiles directory; prefer delayed mode where both exist
+    let mut file_names: Vec<String> = Vec::new();
+    if let Ok(entries) = fs::read_dir(data_directory) {
+        for entry in entries {
+            if let Ok(entry) = entry {
+                if let Some(wmo) = entry.file_name().to_str() {
+                    let delayed = format!("{}/{}/{}_Dtraj.nc", data_directory, wmo, wmo);
+                    let realtime = format!("{}/{}/{}_Rtraj.nc", data_directory, wmo, wmo);
+                    if fs::metadata(&delayed).is_ok() {
+                        file_names.push(delayed);
+                    } else if fs::metadata(&realtime).is_ok() {
+                        file_names.push(realtime);
+                    }
+                }
+            }
+        }
+    }
+    file_names
+}
+
+pub fn extract_trajectory(file_name: &str) -> Result<Vec<TrajectorySchema>, Box<dyn Error>> {
+    let file = netcdf::open(file_name)?;
+    let N_MEASUREMENT: usize = dimension("N_MEASUREMENT", &file);
+    let N_CYCLE: usize = dimension("N_CYCLE", &file);
+    let N_PARAM: usize = dimension("N_PARAM", &file);
+
+    let PLATFORM_NUMBER: String = unpack_string("PLATFORM_NUMBER", 8, [0..8].into(), &file);
+    let namesize: usize = file.variable("TRAJECTORY_PARAMETERS")
+        .and_then(|variable| variable.dimensions().last().map(|d| d.len()))
+        .unwrap_or(0);
+    let TRAJECTORY_PARAMETERS: Vec<String> = if namesize > 0 {
+        unpack_string_array("TRAJECTORY_PARAMETERS", namesize, N_PARAM, [0..N_PARAM, 0..namesize].into(), &file)
+    } else {
+        vec![]
+    };
+
+    // measurements, tagged with the cycle they belong to; adjusted cycle numbers win in delayed mode files
+    let cycle_name = if file.variable("CYCLE_NUMBER_ADJUSTED").is_some() { "CYCLE_NUMBER_ADJUSTED" } else { "CYCLE_NUMBER" };
+    let CYCLE_NUMBER: Vec<Option<i32>> = integer_values(cycle_name, N_MEASUREMENT, &file)?;
+    let JULD: Vec<Option<f64>> = values("JULD", N_MEASUREMENT, &file)?;
+    let JULD_QC: Vec<String> = unpack_string_array("JULD_QC", 1, N_MEASUREMENT, [0..N_MEASUREMENT].into(), &file);
+    let LATITUDE: Vec<Option<f64>> = values("LATITUDE", N_MEASUREMENT, &file)?;
+    let LONGITUDE: Vec<Option<f64>> = values("LONGITUDE", N_MEASUREMENT, &file)?;
+    let POSITION_ACCURACY: Vec<String> = unpack_string_array("POSITION_ACCURACY", 1, N_MEASUREMENT, [0..N_MEASUREMENT].into(), &file);
+    let POSITION_QC: Vec<String> = unpack_string_array("POSITION_QC", 1, N_MEASUREMENT, [0..N_MEASUREMENT].into(), &file);
+    let MEASUREMENT_CODE: Vec<Option<i32>> = integer_values("MEASUREMENT_CODE", N_MEASUREMENT, &file)?;
+    let mut parameter_data: Vec<(String, Vec<Option<f64>>)> = Vec::new();
+    for param in TRAJECTORY_PARAMETERS.iter().filter(|param| !param.is_empty()) {
+        parameter_data.push((param.clone(), values(param, N_MEASUREMENT, &file)?));
+    }
+
+    let mut cycles: BTreeMap<i32, TrajectorySchema> = BTreeMap::new();
+    let cycle = |number: i32| -> TrajectorySchema {
+        TrajectorySchema {
+            _id: format!("{}_{:03}", PLATFORM_NUMBER, number),
+            PLATFORM_NUMBER: PLATFORM_NUMBER.clone(),
+            CYCLE_NUMBER: number,
+            DATA_MODE: DataMode::Blank,
+            CONFIG_MISSION_NUMBER: None,
+            GROUNDED: String::new(),
+            timing: HashMap::new(),
+            measurements: Vec::new(),
+        }
+    };
+
+    for i in 0..N_MEASUREMENT {
+        // rows with no cycle or measurement code are unused
+        let (Some(cycle_number), Some(measurement_code)) = (CYCLE_NUMBER[i], MEASUREMENT_CODE[i]) else { continue };
+        let measurement = TrajectoryMeasurement {
+            JULD: JULD[i],
+            JULD_QC: QcFlag::parse(&JULD_QC[i]),
+            LATITUDE: latitude(LATITUDE[i]),
+            LONGITUDE: longitude(LONGITUDE[i]),
+            POSITION_ACCURACY: POSITION_ACCURACY[i].clone(),
+            POSITION_QC: QcFlag::parse(&POSITION_QC[i]),
+            MEASUREMENT_CODE: measurement_code,
+            data: parameter_data.iter()
+                .filter_map(|(param, data)| data[i].map(|value| (param.clone(), value)))
+                .collect(),
+        };
+        cycles.entry(cycle_number).or_insert_with(|| cycle(cycle_number)).measurements.push(measurement);
+    }
+
+    // per-cycle timing and configuration
+    let index_name = if file.variable("CYCLE_NUMBER_INDEX_ADJUSTED").is_some() { "CYCLE_NUMBER_INDEX_ADJUSTED" } else { "CYCLE_NUMBER_INDEX" };
+    let CYCLE_NUMBER_INDEX: Vec<Option<i32>> = integer_values(index_name, N_CYCLE, &file)?;
+    let DATA_MODE: Vec<String> = unpack_string_array("DATA_MODE", 1, N_CYCLE, [0..N_CYCLE].into(), &file);
+    let GROUNDED: Vec<String> = unpack_string_array("GROUNDED", 1, N_CYCLE, [0..N_CYCLE].into(), &file);
+    let CONFIG_MISSION_NUMBER: Vec<Option<i32>> = integer_values("CONFIG_MISSION_NUMBER", N_CYCLE, &file)?;
+    let mut timing_data: Vec<(&str, Vec<Option<f64>>)> = Vec::new();
+    for name in CYCLE_TIMES {
+        if file.variable(name).is_some() {
+            timing_data.push((name, values(name, N_CYCLE, &file)?));
+        }
+    }
+
+    for c in 0..N_CYCLE {
+        let Some(cycle_number) = CYCLE_NUMBER_INDEX[c] else { continue };
+        let entry = cycles.entry(cycle_number).or_insert_with(|| cycle(cycle_number));
+        entry.DATA_MODE = DataMode::parse(&DATA_MODE[c]);
+        entry.GROUNDED = GROUNDED[c].clone();
+        entry.CONFIG_MISSION_NUMBER = CONFIG_MISSION_NUMBER[c];
+        entry.timing = timing_data.iter()
+            .filter_map(|(name, data)| data[c].map(|value| (name.to_string(), value)))
+            .collect();
+    }
+
+    Ok(cycles.into_values().collect())
+}
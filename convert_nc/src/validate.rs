@@ -0,0 +1,312 @@
+// checks profile files against the Argo user manual's format rules and reference tables,
+// without converting or writing anything
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+
+use argo_schema::{DataMode, Direction, QcFlag};
+
+use crate::{archive, detect_file_kind, unpack_optional_values, unpack_string, unpack_string_array, FileKind};
+
+// variables every profile file carries, core, B or synthetic
+const REQUIRED_VARIABLES: [&str; 27] = [
+    "DATA_TYPE",
+    "FORMAT_VERSION",
+    "HANDBOOK_VERSION",
+    "REFERENCE_DATE_TIME",
+    "DATE_CREATION",
+    "DATE_UPDATE",
+    "PLATFORM_NUMBER",
+    "PROJECT_NAME",
+    "PI_NAME",
+    "STATION_PARAMETERS",
+    "CYCLE_NUMBER",
+    "DIRECTION",
+    "DATA_CENTRE",
+    "DC_REFERENCE",
+    "DATA_STATE_INDICATOR",
+    "PLATFORM_TYPE",
+    "FLOAT_SERIAL_NO",
+    "FIRMWARE_VERSION",
+    "WMO_INST_TYPE",
+    "JULD",
+    "JULD_QC",
+    "JULD_LOCATION",
+    "LATITUDE",
+    "LONGITUDE",
+    "POSITION_QC",
+    "POSITIONING_SYSTEM",
+    "CONFIG_MISSION_NUMBER",
+];
+
+// fixed-length string dimensions and their sizes; N_PROF, N_PARAM and N_LEVELS vary by file
+const STRING_DIMENSIONS: [(&str, usize); 8] = [
+    ("DATE_TIME", 14),
+    ("STRING256", 256),
+    ("STRING64", 64),
+    ("STRING32", 32),
+    ("STRING16", 16),
+    ("STRING8", 8),
+    ("STRING4", 4),
+    ("STRING2", 2),
+];
+
+// reference table 3, physical parameter names, core and BGC, including the intermediate parameters B-files carry.
+// wavelength-resolved parameters are listed once, under WAVELENGTH_PARAMETERS; --parameter-table loads the
+// current official list in place of this one
+const PARAMETER_NAMES: [&str; 60] = [
+    // CTD
+    "PRES", "TEMP", "PSAL", "CNDC", "TEMP_CNDC", "NB_SAMPLE_CTD", "MTIME",
+    // oxygen
+    "DOXY", "TEMP_DOXY", "MOLAR_DOXY", "PPOX_DOXY", "MLPL_DOXY",
+    "BPHASE_DOXY", "DPHASE_DOXY", "TPHASE_DOXY", "RPHASE_DOXY", "C1PHASE_DOXY", "C2PHASE_DOXY",
+    "PHASE_DELAY_DOXY", "TEMP_VOLTAGE_DOXY", "VOLTAGE_DOXY", "FREQUENCY_DOXY", "COUNT_DOXY", "LED_FLASHING_COUNT_DOXY",
+    // chlorophyll-a and CDOM fluorescence
+    "CHLA", "CHLA435", "CHLA_FLUORESCENCE", "FLUORESCENCE_CHLA", "FLUORESCENCE_CHLA435", "FLUORESCENCE_VOLTAGE_CHLA",
+    "TEMP_CPU_CHLA", "CDOM", "FLUORESCENCE_CDOM",
+    // turbidity
+    "TURBIDITY", "SIDE_SCATTERING_TURBIDITY",
+    // nitrate and bisulfide
+    "NITRATE", "BISULFIDE", "MOLAR_NITRATE", "TEMP_NITRATE", "TEMP_SPECTROPHOTOMETER_NITRATE", "HUMIDITY_NITRATE",
+    "UV_INTENSITY_NITRATE", "UV_INTENSITY_DARK_NITRATE", "UV_INTENSITY_DARK_SEAWATER_NITRATE", "FIT_ERROR_NITRATE",
+    // pH
+    "PH_IN_SITU_TOTAL", "PH_IN_SITU_FREE", "VRS_PH", "VRS_STD_PH", "VK_PH", "IK_PH", "IB_PH", "TEMP_PH",
+    // radiometry
+    "DOWNWELLING_PAR", "RAW_DOWNWELLING_PAR",
+    // particle size spectra
+    "NB_SIZE_SPECTRA_PARTICLES", "GREY_SIZE_SPECTRA_PARTICLES", "TEMP_PARTICLES",
+    "BLACK_NB_SIZE_SPECTRA_PARTICLES", "BLACK_TEMP_PARTICLES",
+];
+
+// families with one parameter per wavelength in nm, like BBP700 or DOWN_IRRADIANCE490
+const WAVELENGTH_PARAMETERS: [&str; 8] = [
+    "BBP", "BETA_BACKSCATTERING",
+    "CP", "TRANSMITTANCE_PARTICLE_BEAM_ATTENUATION",
+    "DOWN_IRRADIANCE", "RAW_DOWNWELLING_IRRADIANCE",
+    "UP_RADIANCE", "RAW_UPWELLING_RADIANCE",
+];
+
+// the parameter names a file is checked against, by default the table above
+pub fn parameter_names() -> HashSet<String> {
+    PARAMETER_NAMES.iter().map(|name| name.to_string()).collect()
+}
+
+// the official parameter list, e.g. the parameter column of the argo-parameters list exported as CSV: the first field
+// of each line that's a parameter name; headers, comments and blank lines are passed over
+pub fn read_parameter_table(file_name: &str) -> Result<HashSet<String>, Box<dyn Error>> {
+    let names: HashSet<String> = fs::read_to_string(file_name)?.lines()
+        .filter_map(|line| line.split([',', ';', '\t']).next())
+        .map(|field| field.trim().trim_matches('"').to_string())
+        .filter(|field| is_parameter_name(field))
+        .collect();
+    if names.is_empty() {
+        return Err(format!("{} lists no parameter names", file_name).into());
+    }
+    Ok(names)
+}
+
+fn is_parameter_name(field: &str) -> bool {
+    field.starts_with(|c: char| c.is_ascii_uppercase()) && field.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+// the A-F grades for PROFILE_<PARAM>_QC; measurement flags, data modes and directions are checked by argo_schema
+const PROFILE_QC_FLAGS: [&str; 7] = ["", "A", "B", "C", "D", "E", "F"];
+
+fn is_profile_qc_flag(flag: &str) -> bool {
+    PROFILE_QC_FLAGS.contains(&flag)
+}
+
+fn known_parameter(name: &str, parameters: &HashSet<String>) -> bool {
+    // _STD and _MED are the standard deviation and median variants of a parameter
+    let name = name.strip_suffix("_STD").or_else(|| name.strip_suffix("_MED")).unwrap_or(name);
+    if parameters.contains(name) {
+        return true;
+    }
+    // a second or third sensor of the same kind is numbered, as in DOXY2 or BBP700_2
+    let unnumbered = match name.as_bytes() {
+        [.., b'_', b'2'..=b'9'] => &name[..name.len() - 2],
+        [.., c, b'2'..=b'9'] if c.is_ascii_alphabetic() => &name[..name.len() - 1],
+        _ => name,
+    };
+    if unnumbered != name && parameters.contains(unnumbered) {
+        return true;
+    }
+    let family = unnumbered.trim_end_matches(|c: char| c.is_ascii_digit());
+    unnumbered.len() - family.len() == 3 && WAVELENGTH_PARAMETERS.contains(&family)
+}
+
+fn dimension_names(name: &str, file: &netcdf::File) -> Option<Vec<String>> {
+    file.variable(name).map(|variable| variable.dimensions().iter().map(|d| d.name()).collect())
+}
+
+fn check_flags(name: &str, values: &[String], is_valid: fn(&str) -> bool, violations: &mut Vec<String>) {
+    for (i, value) in values.iter().enumerate() {
+        if !is_valid(value) {
+            violations.push(format!("{}[{}] is {:?}, which isn't a valid flag", name, i, value));
+        }
+    }
+}
+
+pub fn validate_profile_file(file_name: &str, parameters: &HashSet<String>) -> Result<Vec<String>, Box<dyn Error>> {
+    if archive::is_gzip(file_name) {
+        // compressed files are checked from memory, the way they're converted
+        let contents = fs::read(file_name)?;
+        let contents = archive::decompress(file_name, &contents)?;
+        let file = netcdf::open_mem(Some(file_name), &contents)?;
+        validate(file_name, &file, parameters)
+    } else {
+        validate(file_name, &netcdf::open(file_name)?, parameters)
+    }
+}
+
+fn validate(file_name: &str, file: &netcdf::File, parameters: &HashSet<String>) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut violations: Vec<String> = Vec::new();
+
+    // dimensions
+    let dimension = |name: &str| file.dimension(name).map(|d| d.len());
+    for (name, size) in STRING_DIMENSIONS {
+        match dimension(name) {
+            None => violations.push(format!("missing dimension {}", name)),
+            Some(len) if len != size => violations.push(format!("dimension {} is {}, expected {}", name, len, size)),
+            _ => {},
+        }
+    }
+    let (N_PROF, N_PARAM, N_LEVELS) = match (dimension("N_PROF"), dimension("N_PARAM"), dimension("N_LEVELS")) {
+        (Some(N_PROF), Some(N_PARAM), Some(N_LEVELS)) => (N_PROF, N_PARAM, N_LEVELS),
+        _ => {
+            violations.push("missing N_PROF, N_PARAM or N_LEVELS, so the rest of the file can't be checked".to_string());
+            return Ok(violations);
+        }
+    };
+
+    // required variables; core files have one DATA_MODE per profile, B and synthetic files one per parameter
+    let DATA_TYPE = unpack_string("DATA_TYPE", 16, [..16].into(), file);
+    let file_kind = detect_file_kind(file_name, &DATA_TYPE);
+    let mode_variable = if file_kind == FileKind::Core { "DATA_MODE" } else { "PARAMETER_DATA_MODE" };
+    for name in REQUIRED_VARIABLES.iter().chain([&mode_variable]) {
+        if file.variable(name).is_none() {
+            violations.push(format!("missing variable {}", name));
+        }
+    }
+
+    // STATION_PARAMETERS is [N_PROF, N_PARAM, STRING16] in core files and STRING64 in B and synthetic files
+    let namedim = dimension_names("STATION_PARAMETERS", file).and_then(|names| names.last().cloned()).unwrap_or_default();
+    let namesize = match namedim.as_str() {
+        "STRING16" => 16,
+        "STRING64" => 64,
+        _ => {
+            violations.push(format!("STATION_PARAMETERS is dimensioned by {:?}, expected STRING16 or STRING64", namedim));
+            return Ok(violations);
+        }
+    };
+
+    let mut checked: HashSet<String> = HashSet::new();
+    for pindex in 0..N_PROF {
+        let p = pindex..pindex+1;
+        let STATION_PARAMETERS = unpack_string_array("STATION_PARAMETERS", namesize, N_PARAM, [p.clone(), 0..N_PARAM, 0..namesize].into(), file);
+
+        // N_PARAM is sized for the profile with the most parameters, so blanks may pad the end but not leave gaps
+        if let Some(last) = STATION_PARAMETERS.iter().rposition(|param| !param.is_empty()) {
+            for (i, param) in STATION_PARAMETERS[..last].iter().enumerate() {
+                if param.is_empty() {
+                    violations.push(format!("profile {}: STATION_PARAMETERS[{}] is blank between named parameters", pindex, i));
+                }
+            }
+        } else {
+            violations.push(format!("profile {}: STATION_PARAMETERS is empty", pindex));
+        }
+
+        if file_kind == FileKind::Core {
+            let DATA_MODE = unpack_string("DATA_MODE", 1, [p.clone()].into(), file);
+            check_flags(&format!("profile {}: DATA_MODE", pindex), &[DATA_MODE], DataMode::is_valid, &mut violations);
+        } else {
+            let PARAMETER_DATA_MODE = unpack_string_array("PARAMETER_DATA_MODE", 1, N_PARAM, [p.clone(), 0..N_PARAM].into(), file);
+            let named: Vec<String> = PARAMETER_DATA_MODE.into_iter()
+                .zip(&STATION_PARAMETERS)
+                .filter(|(_, param)| !param.is_empty())
+                .map(|(mode, _)| mode)
+                .collect();
+            check_flags(&format!("profile {}: PARAMETER_DATA_MODE", pindex), &named, DataMode::is_valid, &mut violations);
+        }
+        let DIRECTION = unpack_string("DIRECTION", 1, [p.clone()].into(), file);
+        check_flags(&format!("profile {}: DIRECTION", pindex), &[DIRECTION], Direction::is_valid, &mut violations);
+        for name in ["JULD_QC", "POSITION_QC"] {
+            let flag = unpack_string(name, 1, [p.clone()].into(), file);
+            check_flags(&format!("profile {}: {}", pindex, name), &[flag], QcFlag::is_valid, &mut violations);
+        }
+
+        for param in STATION_PARAMETERS.iter().filter(|param| !param.is_empty()) {
+            if !known_parameter(param, parameters) {
+                violations.push(format!("profile {}: {} isn't in the parameter reference table", pindex, param));
+            }
+            // the per-variable checks cover every profile, so each parameter is checked the first time it's listed
+            if !checked.insert(param.clone()) {
+                continue;
+            }
+            match dimension_names(param, file) {
+                None => violations.push(format!("{} is listed in STATION_PARAMETERS but has no variable", param)),
+                Some(names) if names != ["N_PROF", "N_LEVELS"] => violations.push(format!("{} is dimensioned {:?}, expected [N_PROF, N_LEVELS]", param, names)),
+                _ => {},
+            }
+            for suffix in ["_QC", "_ADJUSTED_QC"] {
+                let qc_name = format!("{}{}", param, suffix);
+                if file.variable(&qc_name).is_some() {
+                    for qindex in 0..N_PROF {
+                        let flags = unpack_string_array(&qc_name, 1, N_LEVELS, [qindex..qindex+1, 0..N_LEVELS].into(), file);
+                        check_flags(&format!("profile {}: {}", qindex, qc_name), &flags, QcFlag::is_valid, &mut violations);
+                    }
+                }
+            }
+            let profile_qc_name = format!("PROFILE_{}_QC", param);
+            if file.variable(&profile_qc_name).is_some() {
+                let flags = unpack_string_array(&profile_qc_name, 1, N_PROF, [0..N_PROF].into(), file);
+                check_flags(&profile_qc_name, &flags, is_profile_qc_flag, &mut violations);
+            }
+        }
+
+        // levels are stored in order of increasing pressure, whichever way the float was moving
+        if let Some(variable) = file.variable("PRES") {
+            // fill values are skipped, keeping each pressure's level index
+            let pressures: Vec<(usize, f64)> = unpack_optional_values(&variable, [p.clone(), 0..N_LEVELS].into())?.into_iter()
+                .enumerate()
+                .filter_map(|(level, pressure)| pressure.map(|pressure| (level, pressure)))
+                .collect();
+            if let Some(pair) = pressures.windows(2).find(|pair| pair[1].1 <= pair[0].1) {
+                violations.push(format!("profile {}: PRES isn't increasing at level {} ({} then {})", pindex, pair[1].0, pair[0].1, pair[1].1));
+            }
+        }
+    }
+
+    Ok(violations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reference_table_names() {
+        let parameters = parameter_names();
+        for name in [
+            "PRES", "TEMP_STD", "PSAL_MED", "DOXY2", "TEMP_DOXY3", "MOLAR_NITRATE", "TEMP_NITRATE", "FIT_ERROR_NITRATE",
+            "TEMP_SPECTROPHOTOMETER_NITRATE", "HUMIDITY_NITRATE", "BBP700", "BBP700_2", "DOWN_IRRADIANCE490", "CP660",
+        ] {
+            assert!(known_parameter(name, &parameters), "{} isn't known", name);
+        }
+        for name in ["TEMPERATURE", "BBP70", "DOXY_X", "CHLA_2X"] {
+            assert!(!known_parameter(name, &parameters), "{} is known", name);
+        }
+    }
+
+    #[test]
+    fn official_parameter_list() {
+        let file_name = std::env::temp_dir().join(format!("argo-parameters-{}.csv", std::process::id()));
+        fs::write(&file_name, "parameter name,long name,units\n\"PRES\",Sea water pressure,decibar\nNEW_PARAMETER,A parameter added later,1\n\n").unwrap();
+        let parameters = read_parameter_table(file_name.to_str().unwrap()).unwrap();
+        fs::remove_file(&file_name).unwrap();
+        assert_eq!(parameters, HashSet::from(["PRES".to_string(), "NEW_PARAMETER".to_string()]));
+        assert!(known_parameter("NEW_PARAMETER", &parameters));
+        assert!(!known_parameter("TEMP", &parameters));
+    }
+}